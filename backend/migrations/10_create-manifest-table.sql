@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS manifest (
+  container_id INTEGER PRIMARY KEY NOT NULL,
+  contents TEXT NOT NULL,
+  printed_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(container_id) REFERENCES container(id) ON DELETE CASCADE
+)
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS label_print (
+  kind TEXT NOT NULL CHECK (kind IN ('container', 'item', 'unit')),
+  entity_id INTEGER NOT NULL,
+  payload TEXT NOT NULL,
+  template TEXT NOT NULL,
+  text TEXT NOT NULL,
+  printed_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (kind, entity_id)
+);
+
+CREATE TRIGGER IF NOT EXISTS container_label_print_delete AFTER DELETE ON container
+BEGIN
+  DELETE FROM label_print WHERE kind = 'container' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS item_label_print_delete AFTER DELETE ON item
+BEGIN
+  DELETE FROM label_print WHERE kind = 'item' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS unit_label_print_delete AFTER DELETE ON unit
+BEGIN
+  DELETE FROM label_print WHERE kind = 'unit' AND entity_id = OLD.id;
+END;
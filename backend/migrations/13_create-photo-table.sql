@@ -0,0 +1,67 @@
+-- Any number of photos for each container, item and item location, one of
+-- them the primary photo shown by default.
+CREATE TABLE IF NOT EXISTS photo (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL CHECK (kind IN ('container', 'item', 'item_location')),
+  entity_id INTEGER NOT NULL,
+  data BLOB NOT NULL,
+  content_type TEXT NOT NULL,
+  caption TEXT,
+  is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+  -- upright, after EXIF orientation - unknown for photos stored before
+  width INTEGER,
+  height INTEGER,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS photo_entity ON photo(kind, entity_id);
+CREATE UNIQUE INDEX IF NOT EXISTS photo_primary ON photo(kind, entity_id) WHERE is_primary;
+
+-- Scaled down copies for list views, made on upload or when first asked for
+CREATE TABLE IF NOT EXISTS photo_thumbnail (
+  photo_id INTEGER NOT NULL,
+  size INTEGER NOT NULL,
+  data BLOB NOT NULL,
+  PRIMARY KEY (photo_id, size),
+  FOREIGN KEY(photo_id) REFERENCES photo(id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER IF NOT EXISTS container_photo_delete AFTER DELETE ON container
+BEGIN
+  DELETE FROM photo WHERE kind = 'container' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS item_photo_delete AFTER DELETE ON item
+BEGIN
+  DELETE FROM photo WHERE kind = 'item' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS item_location_photo_delete AFTER DELETE ON item_location
+BEGIN
+  DELETE FROM photo WHERE kind = 'item_location' AND entity_id = OLD.id;
+END;
+
+-- the single photos items and containers had become their primary photos,
+-- typed by their first bytes since their type was not kept
+INSERT INTO photo (kind, entity_id, data, content_type, is_primary)
+SELECT 'container', id, photo, CASE
+  WHEN hex(substr(photo, 1, 8)) = '89504E470D0A1A0A' THEN 'image/png'
+  WHEN hex(substr(photo, 1, 3)) = 'FFD8FF' THEN 'image/jpeg'
+  WHEN substr(photo, 1, 4) = CAST('GIF8' AS BLOB) THEN 'image/gif'
+  WHEN substr(photo, 1, 4) = CAST('RIFF' AS BLOB) AND substr(photo, 9, 4) = CAST('WEBP' AS BLOB) THEN 'image/webp'
+  ELSE 'application/octet-stream'
+END, TRUE
+FROM container WHERE photo IS NOT NULL;
+
+INSERT INTO photo (kind, entity_id, data, content_type, is_primary)
+SELECT 'item', id, photo, CASE
+  WHEN hex(substr(photo, 1, 8)) = '89504E470D0A1A0A' THEN 'image/png'
+  WHEN hex(substr(photo, 1, 3)) = 'FFD8FF' THEN 'image/jpeg'
+  WHEN substr(photo, 1, 4) = CAST('GIF8' AS BLOB) THEN 'image/gif'
+  WHEN substr(photo, 1, 4) = CAST('RIFF' AS BLOB) AND substr(photo, 9, 4) = CAST('WEBP' AS BLOB) THEN 'image/webp'
+  ELSE 'application/octet-stream'
+END, TRUE
+FROM item WHERE photo IS NOT NULL;
+
+ALTER TABLE container DROP COLUMN photo;
+ALTER TABLE item DROP COLUMN photo;
@@ -0,0 +1,59 @@
+-- Photo and thumbnail images are kept in the blob store, a directory of
+-- files named by the SHA-256 of their contents, and referenced here by that
+-- hash. Images still in `data` are moved out when the server starts, so the
+-- tables are rebuilt to let `data` be empty.
+CREATE TABLE photo_old AS SELECT * FROM photo;
+CREATE TABLE photo_thumbnail_old AS SELECT * FROM photo_thumbnail;
+-- so ids of photos deleted before now are not handed out again
+CREATE TABLE photo_sequence AS SELECT seq FROM sqlite_sequence WHERE name = 'photo';
+
+DROP TABLE photo_thumbnail;
+DROP TABLE photo;
+
+CREATE TABLE photo (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL CHECK (kind IN ('container', 'item', 'item_location')),
+  entity_id INTEGER NOT NULL,
+  -- only until moved to the blob store
+  data BLOB,
+  hash TEXT,
+  content_type TEXT NOT NULL,
+  caption TEXT,
+  is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+  -- upright, after EXIF orientation - unknown for photos stored before
+  width INTEGER,
+  height INTEGER,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CHECK ((data IS NULL) <> (hash IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS photo_entity ON photo(kind, entity_id);
+CREATE UNIQUE INDEX IF NOT EXISTS photo_primary ON photo(kind, entity_id) WHERE is_primary;
+
+CREATE TABLE photo_thumbnail (
+  photo_id INTEGER NOT NULL,
+  size INTEGER NOT NULL,
+  -- only until moved to the blob store
+  data BLOB,
+  hash TEXT,
+  PRIMARY KEY (photo_id, size),
+  FOREIGN KEY(photo_id) REFERENCES photo(id) ON DELETE CASCADE,
+  CHECK ((data IS NULL) <> (hash IS NULL))
+);
+
+INSERT INTO photo (id, kind, entity_id, data, content_type, caption, is_primary, width, height, created_at)
+SELECT id, kind, entity_id, data, content_type, caption, is_primary, width, height, created_at
+FROM photo_old;
+
+INSERT INTO photo_thumbnail (photo_id, size, data)
+SELECT photo_id, size, data FROM photo_thumbnail_old;
+
+UPDATE sqlite_sequence SET seq = (SELECT seq FROM photo_sequence)
+WHERE name = 'photo' AND seq < (SELECT seq FROM photo_sequence);
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'photo', seq FROM photo_sequence
+WHERE NOT EXISTS (SELECT 1 FROM sqlite_sequence WHERE name = 'photo');
+
+DROP TABLE photo_old;
+DROP TABLE photo_thumbnail_old;
+DROP TABLE photo_sequence;
@@ -0,0 +1,29 @@
+-- Files attached to containers, items and units - datasheets, manuals,
+-- receipts and the like. The contents are in the blob store.
+CREATE TABLE IF NOT EXISTS attachment (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind TEXT NOT NULL CHECK (kind IN ('container', 'item', 'unit')),
+  entity_id INTEGER NOT NULL,
+  file_name TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  hash TEXT NOT NULL,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS attachment_entity ON attachment(kind, entity_id);
+
+CREATE TRIGGER IF NOT EXISTS container_attachment_delete AFTER DELETE ON container
+BEGIN
+  DELETE FROM attachment WHERE kind = 'container' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS item_attachment_delete AFTER DELETE ON item
+BEGIN
+  DELETE FROM attachment WHERE kind = 'item' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS unit_attachment_delete AFTER DELETE ON unit
+BEGIN
+  DELETE FROM attachment WHERE kind = 'unit' AND entity_id = OLD.id;
+END;
@@ -0,0 +1,8 @@
+-- Perceptual hashes of photos, for finding items by what they look like.
+-- Photos that cannot be read as images have no hash, so they are not tried
+-- again.
+CREATE TABLE IF NOT EXISTS photo_hash (
+  photo_id INTEGER PRIMARY KEY,
+  dhash INTEGER,
+  FOREIGN KEY(photo_id) REFERENCES photo(id) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS item_barcode (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  item_id INTEGER NOT NULL,
+  code TEXT NOT NULL UNIQUE,
+  symbology TEXT NOT NULL,
+  FOREIGN KEY(item_id) REFERENCES item(id) ON DELETE CASCADE
+)
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS kit_component (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  kit_item_id INTEGER NOT NULL,
+  component_item_id INTEGER NOT NULL,
+  quantity INTEGER NOT NULL CHECK (quantity > 0),
+  UNIQUE(kit_item_id, component_item_id),
+  FOREIGN KEY(kit_item_id) REFERENCES item(id) ON DELETE CASCADE,
+  FOREIGN KEY(component_item_id) REFERENCES item(id) ON DELETE CASCADE
+)
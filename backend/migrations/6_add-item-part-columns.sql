@@ -0,0 +1,3 @@
+ALTER TABLE item ADD COLUMN mpn TEXT;
+ALTER TABLE item ADD COLUMN value TEXT;
+ALTER TABLE item ADD COLUMN footprint TEXT;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS unit (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  item_id INTEGER NOT NULL,
+  container_id INTEGER NOT NULL,
+  serial_number TEXT NOT NULL,
+  purchase_date TEXT CHECK (purchase_date IS NULL OR date(purchase_date) IS purchase_date),
+  condition TEXT,
+  note TEXT,
+  UNIQUE(item_id, serial_number),
+  FOREIGN KEY(item_id) REFERENCES item(id) ON DELETE CASCADE,
+  FOREIGN KEY(container_id) REFERENCES container(id) ON DELETE CASCADE
+)
@@ -0,0 +1,2 @@
+ALTER TABLE item_location ADD COLUMN lot TEXT;
+ALTER TABLE item_location ADD COLUMN expires_on TEXT CHECK (expires_on IS NULL OR date(expires_on) IS expires_on);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS short_code (
+  code TEXT PRIMARY KEY NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('container', 'item', 'unit')),
+  entity_id INTEGER NOT NULL,
+  UNIQUE(kind, entity_id)
+);
+
+CREATE TRIGGER IF NOT EXISTS container_short_code_delete AFTER DELETE ON container
+BEGIN
+  DELETE FROM short_code WHERE kind = 'container' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS item_short_code_delete AFTER DELETE ON item
+BEGIN
+  DELETE FROM short_code WHERE kind = 'item' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS unit_short_code_delete AFTER DELETE ON unit
+BEGIN
+  DELETE FROM short_code WHERE kind = 'unit' AND entity_id = OLD.id;
+END;
@@ -0,0 +1,291 @@
+use std::sync::Arc;
+
+use crate::rocket::futures::TryStreamExt;
+use rocket::data::ToByteUnit;
+use rocket::form::{self, DataField, Form, FromFormField};
+use rocket::http::{ContentType, Header, RawStr};
+use rocket::request::FromParam;
+use rocket::response::status::Created;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::blob::StoreError;
+use crate::{AppState, Db};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// The kinds of thing files can be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Container,
+    Item,
+    Unit,
+}
+
+impl AttachmentKind {
+    /// How the kind is stored in the `attachment` table, which is also the
+    /// first segment of its entities' routes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttachmentKind::Container => "container",
+            AttachmentKind::Item => "item",
+            AttachmentKind::Unit => "unit",
+        }
+    }
+
+    /// Whether the entity files would be attached to exists.
+    async fn exists(&self, conn: &mut SqliteConnection, id: i64) -> Result<bool, sqlx::Error> {
+        let found = match self {
+            AttachmentKind::Container => sqlx::query!("SELECT id FROM container WHERE id = ?", id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .is_some(),
+            AttachmentKind::Item => sqlx::query!("SELECT id FROM item WHERE id = ?", id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .is_some(),
+            AttachmentKind::Unit => sqlx::query!("SELECT id FROM unit WHERE id = ?", id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .is_some(),
+        };
+        Ok(found)
+    }
+}
+
+impl<'a> FromParam<'a> for AttachmentKind {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        match param {
+            "container" => Ok(AttachmentKind::Container),
+            "item" => Ok(AttachmentKind::Item),
+            "unit" => Ok(AttachmentKind::Unit),
+            _ => Err(param),
+        }
+    }
+}
+
+/// A file attached to a container, item or unit, without its contents -
+/// see `read` for those.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Attachment {
+    pub id: i64,
+    pub file_name: String,
+    pub content_type: String,
+    /// In bytes
+    pub size: i64,
+    pub created_at: String,
+}
+
+/// A file uploaded in a multipart form.
+#[derive(Debug, Clone)]
+pub struct File {
+    pub data: Vec<u8>,
+    pub file_name: String,
+    /// As uploaded, or from the file name's extension if the upload did not
+    /// say
+    pub content_type: ContentType,
+}
+
+/// The form files are attached with - the file in a `file` field.
+#[derive(Debug, FromForm)]
+pub struct AttachmentUpload {
+    pub file: File,
+}
+
+/// The last component of an uploaded file's name, without anything that
+/// would trip up a `Content-Disposition` header.
+fn file_name(raw: Option<&str>) -> String {
+    let name: String = raw
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(255)
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "attachment".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The `Content-Disposition` of a download. PDFs and images open in the
+/// browser; anything else is saved, so that attached HTML and the like is
+/// never run as part of the site.
+fn disposition(file_name: &str, content_type: &ContentType) -> Header<'static> {
+    let inline = *content_type == ContentType::PDF
+        || (content_type.top() == "image" && *content_type != ContentType::SVG);
+    let ascii: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    Header::new(
+        "Content-Disposition",
+        format!(
+            "{}; filename=\"{}\"; filename*=UTF-8''{}",
+            if inline { "inline" } else { "attachment" },
+            ascii,
+            RawStr::new(file_name).percent_encode()
+        ),
+    )
+}
+
+/// A download, with what it should be saved as.
+#[derive(Responder)]
+pub struct Download {
+    body: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+    nosniff: Header<'static>,
+}
+
+async fn fetch(conn: &mut SqliteConnection, id: i64) -> Result<Option<Attachment>, sqlx::Error> {
+    sqlx::query_as!(
+        Attachment,
+        r#"SELECT id AS "id!", file_name, content_type, size, created_at
+        FROM attachment WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+/// Attach a file from a multipart form. Ranked behind `read_qr` and the
+/// like, which it would otherwise collide with.
+#[post("/<kind>/<id>/attachments", data = "<upload>", rank = 3)]
+pub async fn create(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    kind: AttachmentKind,
+    id: i64,
+    upload: Form<AttachmentUpload>,
+) -> Result<Option<Created<Json<Attachment>>>, StoreError> {
+    if !kind.exists(&mut db, id).await? {
+        return Ok(None);
+    }
+    let file = &upload.file;
+    let hash = state.blobs.put(&file.data).await?;
+    let kind = kind.as_str();
+    let content_type = file.content_type.to_string();
+    let size = file.data.len() as i64;
+    let attachment_id = sqlx::query!(
+        "INSERT INTO attachment (kind, entity_id, file_name, content_type, size, hash)
+        VALUES (?, ?, ?, ?, ?, ?)",
+        kind,
+        id,
+        file.file_name,
+        content_type,
+        size,
+        hash
+    )
+    .execute(&mut *db)
+    .await?
+    .last_insert_rowid();
+
+    let attachment = fetch(&mut db, attachment_id).await?;
+    Ok(attachment.map(|attachment| {
+        Created::new(format!("/attachment/{}", attachment_id)).body(Json(attachment))
+    }))
+}
+
+/// The files attached to an entity, oldest first.
+#[get("/<kind>/<id>/attachments", rank = 3)]
+pub async fn list(
+    mut db: Connection<Db>,
+    kind: AttachmentKind,
+    id: i64,
+) -> Result<Option<Json<Vec<Attachment>>>> {
+    if !kind.exists(&mut db, id).await? {
+        return Ok(None);
+    }
+    let kind = kind.as_str();
+    let attachments = sqlx::query_as!(
+        Attachment,
+        r#"SELECT id AS "id!", file_name, content_type, size, created_at
+        FROM attachment WHERE kind = ? AND entity_id = ? ORDER BY id"#,
+        kind,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(Some(Json(attachments)))
+}
+
+/// An attached file's contents, under the name it was uploaded with.
+#[get("/attachment/<id>")]
+pub async fn read(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+) -> Result<Option<Download>, StoreError> {
+    let attachment = match sqlx::query!(
+        "SELECT file_name, content_type, hash FROM attachment WHERE id = ?",
+        id
+    )
+    .fetch_optional(&mut *db)
+    .await?
+    {
+        Some(attachment) => attachment,
+        None => return Ok(None),
+    };
+    let content_type =
+        ContentType::parse_flexible(&attachment.content_type).unwrap_or(ContentType::Binary);
+    let data = state.blobs.get(&attachment.hash).await?;
+
+    Ok(Some(Download {
+        disposition: disposition(&attachment.file_name, &content_type),
+        body: (content_type, data),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    }))
+}
+
+/// Remove an attachment. Its contents stay in the blob store until garbage
+/// collected, as other attachments may have the same ones.
+#[delete("/attachment/<id>")]
+pub async fn delete(mut db: Connection<Db>, id: i64) -> Result<Option<()>> {
+    let result = sqlx::query!("DELETE FROM attachment WHERE id = ?", id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for File {
+    async fn from_data(field: DataField<'r, '_>) -> form::Result<'r, Self> {
+        let limit = field
+            .request
+            .limits()
+            .get("attachment")
+            .unwrap_or_else(|| 50.mebibytes());
+        let data = field.data.open(limit).into_bytes().await?;
+        if !data.is_complete() {
+            return Err((None, Some(limit)).into());
+        }
+        let file_name = file_name(
+            field
+                .file_name
+                .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str()),
+        );
+        let content_type = match field.content_type {
+            content_type if content_type != ContentType::Binary => content_type,
+            _ => file_name
+                .rsplit_once('.')
+                .and_then(|(_, extension)| ContentType::from_extension(extension))
+                .unwrap_or(ContentType::Binary),
+        };
+        Ok(File {
+            data: data.into_inner(),
+            file_name,
+            content_type,
+        })
+    }
+}
@@ -0,0 +1,137 @@
+use crate::rocket::futures::TryStreamExt;
+use rocket_db_pools::sqlx;
+use rocket_db_pools::Connection;
+
+use crate::Db;
+use rocket::response::status::{Conflict, Created};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// A manufacturer barcode printed on an item type - e.g. the EAN-13 on a box
+/// of M3 bolts. An item may have any number of these.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ItemBarcode {
+    pub id: i64,
+    pub item_id: i64,
+    pub code: String,
+    pub symbology: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PostBarcode {
+    pub code: String,
+}
+
+/// Normalize a scanned code so that the same barcode always compares equal,
+/// no matter how the scanner chose to report it. UPC-A codes are widened to
+/// their EAN-13 form, since many scanners report one as the other.
+pub fn normalize(code: &str) -> String {
+    let code = code.trim();
+    if code.len() == 12 && valid_check_digit(code) {
+        format!("0{}", code)
+    } else {
+        code.to_string()
+    }
+}
+
+/// Guess the symbology of a normalized code. Numeric codes with a valid
+/// GS1 check digit are EAN/UPC, everything else is assumed to be Code 128.
+pub fn symbology(code: &str) -> &'static str {
+    match code.len() {
+        8 if valid_check_digit(code) => "ean8",
+        13 if valid_check_digit(code) && code.starts_with('0') => "upca",
+        13 if valid_check_digit(code) => "ean13",
+        _ => "code128",
+    }
+}
+
+/// GS1 mod-10 check digit, shared by EAN-8, UPC-A and EAN-13.
+fn valid_check_digit(code: &str) -> bool {
+    if code.len() < 2 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let digits: Vec<u32> = code.bytes().map(|b| (b - b'0') as u32).collect();
+    let (check, body) = digits.split_last().unwrap();
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    (10 - sum % 10) % 10 == *check
+}
+
+/// Register a barcode to an item. A code can only belong to one item, so one
+/// that is already registered is a conflict.
+#[post("/item/barcode/<item_id>", data = "<barcode>")]
+pub async fn create(
+    mut db: Connection<Db>,
+    item_id: i64,
+    barcode: Json<PostBarcode>,
+) -> Result<Result<Created<Json<ItemBarcode>>, Conflict<String>>> {
+    let code = normalize(&barcode.code);
+    let symbology = symbology(&code);
+    let result = sqlx::query!(
+        "INSERT INTO item_barcode (item_id, code, symbology) VALUES (?, ?, ?)",
+        item_id,
+        code,
+        symbology
+    )
+    .execute(&mut *db)
+    .await;
+    let id = match result {
+        Ok(result) => result.last_insert_rowid(),
+        Err(sqlx::Error::Database(e)) if e.message().starts_with("UNIQUE constraint failed") => {
+            let message = format!("{} is already registered", code);
+            return Ok(Err(Conflict(Some(message))));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(Ok(Created::new("/").body(Json(ItemBarcode {
+        id,
+        item_id,
+        code,
+        symbology: symbology.to_string(),
+    }))))
+}
+
+#[get("/item/barcode/<item_id>")]
+pub async fn list(mut db: Connection<Db>, item_id: i64) -> Result<Json<Vec<ItemBarcode>>> {
+    let barcodes = sqlx::query!(
+        "SELECT id, item_id, code, symbology FROM item_barcode WHERE item_id = ?",
+        item_id
+    )
+    .fetch(&mut *db)
+    .map_ok(|r| ItemBarcode {
+        id: r.id,
+        item_id: r.item_id,
+        code: r.code,
+        symbology: r.symbology,
+    })
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(Json(barcodes))
+}
+
+#[delete("/barcode/<id>")]
+pub async fn delete(mut db: Connection<Db>, id: i64) -> Result<Option<()>> {
+    let result = sqlx::query!("DELETE FROM item_barcode WHERE id = ?", id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+/// Find the item a manufacturer barcode is registered to.
+pub async fn find_item(db: &mut Connection<Db>, code: &str) -> Result<Option<i64>, sqlx::Error> {
+    let code = normalize(code);
+    let row = sqlx::query!("SELECT item_id FROM item_barcode WHERE code = ?", code)
+        .fetch_optional(&mut **db)
+        .await?;
+    Ok(row.map(|r| r.item_id))
+}
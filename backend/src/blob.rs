@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::rocket::futures::TryStreamExt;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::tokio::fs;
+use rocket::State;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::config::BlobConfig;
+use crate::{AppState, Db};
+
+type Result<T, E = StoreError> = std::result::Result<T, E>;
+
+/// Where photos and attachments are kept - a directory of files named by
+/// the SHA-256 of their contents, so the same file uploaded twice is stored
+/// once. The database refers to them by hash.
+pub struct BlobStore {
+    config: BlobConfig,
+}
+
+/// Why the blob store could not be read or written.
+#[derive(Debug)]
+pub struct BlobError(io::Error);
+
+impl From<io::Error> for BlobError {
+    fn from(e: io::Error) -> Self {
+        BlobError(e)
+    }
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blob store: {}", self.0)
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+/// Why a handler using both the database and the blob store failed. Either
+/// way it is a 500, logged as `Debug` logs database errors.
+#[derive(Debug)]
+pub enum StoreError {
+    Database(sqlx::Error),
+    Blob(BlobError),
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(e: sqlx::Error) -> Self {
+        StoreError::Database(e)
+    }
+}
+
+impl From<BlobError> for StoreError {
+    fn from(e: BlobError) -> Self {
+        StoreError::Blob(e)
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Database(e) => e.fmt(f),
+            StoreError::Blob(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for StoreError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        response::Debug(self).respond_to(request)
+    }
+}
+
+/// What a garbage collection removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Collected {
+    pub blobs: u64,
+    pub bytes: u64,
+}
+
+/// The hex SHA-256 of some data, which it is stored under.
+pub fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+impl BlobStore {
+    /// Open the store, creating its directory if need be.
+    pub fn open(config: &BlobConfig) -> Result<Self, String> {
+        std::fs::create_dir_all(&config.path)
+            .map_err(|e| format!("cannot create {}: {}", config.path.display(), e))?;
+        Ok(BlobStore {
+            config: config.clone(),
+        })
+    }
+
+    pub fn config(&self) -> &BlobConfig {
+        &self.config
+    }
+
+    /// Blobs are spread over directories by the first two characters of
+    /// their hash, to keep directories small.
+    fn path(&self, hash: &str) -> PathBuf {
+        self.config.path.join(&hash[..2]).join(hash)
+    }
+
+    /// Store data unless it is already stored, returning its hash.
+    pub async fn put(&self, data: &[u8]) -> Result<String, BlobError> {
+        let hash = hash(data);
+        let path = self.path(&hash);
+        if let Ok(existing) = fs::OpenOptions::new().append(true).open(&path).await {
+            // a fresh reference to an old blob - keep it from being collected
+            // before the reference is saved
+            existing.into_std().await.set_modified(SystemTime::now())?;
+            return Ok(hash);
+        }
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        // written aside and renamed into place, so a blob is never seen half
+        // written
+        let partial = path.with_extension(format!("{}.partial", rand::random::<u32>()));
+        fs::write(&partial, data).await?;
+        fs::rename(&partial, &path).await?;
+        Ok(hash)
+    }
+
+    pub async fn get(&self, hash: &str) -> Result<Vec<u8>, BlobError> {
+        if !is_hash(hash) {
+            let message = format!("{:?} is not a blob hash", hash);
+            return Err(io::Error::new(ErrorKind::InvalidInput, message).into());
+        }
+        Ok(fs::read(self.path(hash)).await?)
+    }
+
+    /// Delete blobs nothing refers to, and files left half written. Files
+    /// newer than the grace period are kept, as they may belong to an upload
+    /// that is still being saved.
+    async fn collect(&self, referenced: &HashSet<String>) -> Result<Collected, BlobError> {
+        let cutoff = SystemTime::now() - Duration::from_secs(self.config.gc_grace);
+        let mut collected = Collected::default();
+        let mut dirs = fs::read_dir(&self.config.path).await?;
+        while let Some(dir) = dirs.next_entry().await? {
+            if !dir.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut blobs = fs::read_dir(dir.path()).await?;
+            while let Some(blob) = blobs.next_entry().await? {
+                let name = blob.file_name().to_string_lossy().into_owned();
+                let collectable = is_hash(&name) || name.ends_with(".partial");
+                if !collectable || referenced.contains(&name) {
+                    continue;
+                }
+                let metadata = blob.metadata().await?;
+                if metadata.modified()? > cutoff {
+                    continue;
+                }
+                match fs::remove_file(blob.path()).await {
+                    Ok(()) => {
+                        collected.blobs += 1;
+                        collected.bytes += metadata.len();
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(collected)
+    }
+}
+
+/// The hashes of every blob the database refers to.
+async fn referenced(conn: &mut SqliteConnection) -> Result<HashSet<String>, sqlx::Error> {
+    sqlx::query!(
+        r#"SELECT hash AS "hash!" FROM photo WHERE hash IS NOT NULL
+        UNION SELECT hash FROM photo_thumbnail WHERE hash IS NOT NULL
+        UNION SELECT hash FROM attachment"#
+    )
+    .fetch(&mut *conn)
+    .map_ok(|r| r.hash)
+    .try_collect()
+    .await
+}
+
+/// Move images still held in the database into the store, then give the
+/// space they took back. Run at startup, after the migrations.
+pub async fn move_inline(conn: &mut SqliteConnection, store: &BlobStore) -> Result<u64> {
+    let mut moved = 0;
+    let photos = sqlx::query!(r#"SELECT id AS "id!" FROM photo WHERE data IS NOT NULL"#)
+        .fetch_all(&mut *conn)
+        .await?;
+    for photo in photos {
+        let row = sqlx::query!(
+            r#"SELECT data AS "data!" FROM photo WHERE id = ?"#,
+            photo.id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let hash = store.put(&row.data).await?;
+        sqlx::query!(
+            "UPDATE photo SET hash = ?, data = NULL WHERE id = ?",
+            hash,
+            photo.id
+        )
+        .execute(&mut *conn)
+        .await?;
+        moved += 1;
+    }
+
+    let thumbnails = sqlx::query!(
+        r#"SELECT photo_id AS "photo_id!", size AS "size!" FROM photo_thumbnail
+        WHERE data IS NOT NULL"#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for thumbnail in thumbnails {
+        let row = sqlx::query!(
+            r#"SELECT data AS "data!" FROM photo_thumbnail WHERE photo_id = ? AND size = ?"#,
+            thumbnail.photo_id,
+            thumbnail.size
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let hash = store.put(&row.data).await?;
+        sqlx::query!(
+            "UPDATE photo_thumbnail SET hash = ?, data = NULL WHERE photo_id = ? AND size = ?",
+            hash,
+            thumbnail.photo_id,
+            thumbnail.size
+        )
+        .execute(&mut *conn)
+        .await?;
+        moved += 1;
+    }
+
+    if moved > 0 {
+        sqlx::query("VACUUM").execute(&mut *conn).await?;
+    }
+    Ok(moved)
+}
+
+/// Delete the blobs nothing refers to any more - e.g. those of deleted
+/// photos and attachments.
+#[post("/blobs/gc")]
+pub async fn gc(mut db: Connection<Db>, state: &State<Arc<AppState>>) -> Result<Json<Collected>> {
+    let referenced = referenced(&mut db).await?;
+    Ok(Json(state.blobs.collect(&referenced).await?))
+}
@@ -0,0 +1,345 @@
+use crate::rocket::futures::TryFutureExt;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::response::status::BadRequest;
+use rocket_db_pools::sqlx::{self, Acquire, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::item_location;
+use crate::Db;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// One grouped line of a KiCad BOM - e.g. R1, R4 and R7 are all 10k 0603
+/// resistors.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BomLine {
+    pub references: Vec<String>,
+    pub value: String,
+    pub footprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpn: Option<String>,
+    /// Quantity per board
+    pub quantity: i64,
+}
+
+/// Where to take some of a BOM line's parts from.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PickLocation {
+    pub item_location_id: i64,
+    pub container_id: i64,
+    pub container_path: Vec<String>,
+    pub quantity: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PickListLine {
+    pub line: BomLine,
+    /// The matched item, if any
+    pub item_id: Option<i64>,
+    pub item_name: Option<String>,
+    /// "mpn", "value_footprint" or "value"
+    pub matched_by: Option<String>,
+    pub required: i64,
+    pub picks: Vec<PickLocation>,
+    pub shortage: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PickList {
+    pub boards: i64,
+    /// Whether stock has actually been taken
+    pub picked: bool,
+    pub lines: Vec<PickListLine>,
+}
+
+/// Normalize a CSV header so the different spellings KiCad versions and BOM
+/// plugins use compare equal.
+fn header_key(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn find_column(headers: &[String], names: &[&str]) -> Option<usize> {
+    headers.iter().position(|h| names.contains(&h.as_str()))
+}
+
+/// Parse a KiCad BOM CSV export, either from the schematic editor or from the
+/// legacy bom_csv_grouped_by_value plugin (which puts a few lines of preamble
+/// before the header row). Lines marked do-not-populate are left out.
+pub fn parse_kicad_csv(csv: &str) -> std::result::Result<Vec<BomLine>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+    let mut records = reader.records();
+
+    let reference_names = ["reference", "references", "ref", "refs", "designator"];
+    let headers: Vec<String> = loop {
+        let record = records
+            .next()
+            .ok_or("no header row with a Reference column")?
+            .map_err(|e| e.to_string())?;
+        let headers: Vec<String> = record.iter().map(header_key).collect();
+        if find_column(&headers, &reference_names).is_some() {
+            break headers;
+        }
+    };
+
+    let reference = find_column(&headers, &reference_names).unwrap();
+    let value = find_column(&headers, &["value", "val"]).ok_or("no Value column")?;
+    let footprint = find_column(&headers, &["footprint"]);
+    let quantity = find_column(&headers, &["qty", "quantity", "quantityperpcb"]);
+    let dnp = find_column(&headers, &["dnp"]);
+    let mpn = find_column(
+        &headers,
+        &[
+            "mpn",
+            "manufacturerpartnumber",
+            "mfrpn",
+            "mfrno",
+            "partnumber",
+        ],
+    );
+
+    let mut lines = Vec::new();
+    for record in records {
+        let record = record.map_err(|e| e.to_string())?;
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .map(str::trim)
+                .unwrap_or("")
+        };
+
+        let references: Vec<String> = field(Some(reference))
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|r| !r.is_empty())
+            .map(String::from)
+            .collect();
+        if references.is_empty() {
+            continue;
+        }
+        if !matches!(
+            field(dnp).to_lowercase().as_str(),
+            "" | "0" | "no" | "false"
+        ) {
+            continue;
+        }
+
+        let quantity = match field(quantity) {
+            "" => references.len() as i64,
+            q => q
+                .parse()
+                .ok()
+                .filter(|q| *q >= 1)
+                .ok_or_else(|| format!("invalid quantity {:?} for {}", q, references[0]))?,
+        };
+
+        lines.push(BomLine {
+            references,
+            value: field(Some(value)).to_string(),
+            footprint: field(footprint).to_string(),
+            mpn: Some(field(mpn).to_string()).filter(|m| !m.is_empty() && m != "~"),
+            quantity,
+        });
+    }
+
+    Ok(lines)
+}
+
+/// Find the item a BOM line refers to - by MPN, then by value and footprint,
+/// then by value alone among items that have no footprint set. Footprints
+/// match with or without their library prefix.
+async fn match_item(
+    conn: &mut SqliteConnection,
+    line: &BomLine,
+) -> Result<Option<(i64, String, &'static str)>, sqlx::Error> {
+    if let Some(mpn) = &line.mpn {
+        let item = sqlx::query!(
+            "SELECT id, name FROM item WHERE mpn = ? COLLATE NOCASE ORDER BY id",
+            mpn
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(item) = item {
+            return Ok(Some((item.id, item.name, "mpn")));
+        }
+    }
+
+    if line.value.is_empty() {
+        return Ok(None);
+    }
+
+    let short_footprint = line
+        .footprint
+        .split_once(':')
+        .map(|(_, name)| name)
+        .unwrap_or(&line.footprint);
+    let item = sqlx::query!(
+        "SELECT id, name FROM item WHERE value = ? COLLATE NOCASE
+        AND (footprint = ? COLLATE NOCASE OR footprint = ? COLLATE NOCASE) ORDER BY id",
+        line.value,
+        line.footprint,
+        short_footprint
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(item) = item {
+        return Ok(Some((item.id, item.name, "value_footprint")));
+    }
+
+    sqlx::query!(
+        "SELECT id, name FROM item WHERE value = ? COLLATE NOCASE AND footprint IS NULL ORDER BY id",
+        line.value
+    )
+    .fetch_optional(&mut *conn)
+    .map_ok(|item| item.map(|item| (item.id, item.name, "value")))
+    .await
+}
+
+/// Build a pick list for `boards` boards. Stock is taken as the list is built,
+/// so lines sharing an item do not count the same stock twice - callers that
+/// only want a preview should run this in a transaction and roll it back.
+/// `None` if the number of parts some line needs does not fit in an `i64`.
+async fn pick_list(
+    conn: &mut SqliteConnection,
+    lines: Vec<BomLine>,
+    boards: i64,
+) -> Result<Option<Vec<PickListLine>>, sqlx::Error> {
+    let mut pick_list = Vec::new();
+    for line in lines {
+        let required = match line.quantity.checked_mul(boards) {
+            Some(required) => required,
+            None => return Ok(None),
+        };
+        let matched = match_item(&mut *conn, &line).await?;
+
+        let (item_id, item_name, matched_by, picks, shortage) = match matched {
+            Some((item_id, name, matched_by)) => {
+                let (picks, shortage) =
+                    item_location::plan_picks(&mut *conn, item_id, required).await?;
+                item_location::apply_picks(&mut *conn, &picks).await?;
+
+                let mut locations = Vec::new();
+                for pick in picks {
+                    locations.push(PickLocation {
+                        item_location_id: pick.item_location_id,
+                        container_id: pick.container_id,
+                        container_path: crate::container::path(&mut *conn, pick.container_id)
+                            .await?,
+                        quantity: pick.quantity,
+                    });
+                }
+                (
+                    Some(item_id),
+                    Some(name),
+                    Some(matched_by),
+                    locations,
+                    shortage,
+                )
+            }
+            None => (None, None, None, Vec::new(), required),
+        };
+
+        pick_list.push(PickListLine {
+            line,
+            item_id,
+            item_name,
+            matched_by: matched_by.map(String::from),
+            required,
+            picks,
+            shortage,
+        });
+    }
+
+    Ok(Some(pick_list))
+}
+
+/// Read a BOM from a request body, refusing one cut off by the `bom` limit
+/// rather than picking for part of it.
+async fn read_csv(data: Data<'_>, limits: &Limits) -> Result<String, String> {
+    let limit = limits.get("bom").unwrap_or_else(|| 1.mebibytes());
+    let csv = data
+        .open(limit)
+        .into_string()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !csv.is_complete() {
+        return Err(format!("BOMs must be under {}", limit));
+    }
+    Ok(csv.into_inner())
+}
+
+async fn handle_kicad(
+    mut db: Connection<Db>,
+    data: Data<'_>,
+    limits: &Limits,
+    boards: Option<i64>,
+    pick: bool,
+) -> Result<Result<Json<PickList>, BadRequest<String>>> {
+    let csv = match read_csv(data, limits).await {
+        Ok(csv) => csv,
+        Err(e) => return Ok(Err(BadRequest(Some(e)))),
+    };
+    let lines = match parse_kicad_csv(&csv) {
+        Ok(lines) => lines,
+        Err(e) => return Ok(Err(BadRequest(Some(e)))),
+    };
+    let boards = boards.unwrap_or(1);
+    if boards < 1 {
+        let message = "boards must be at least 1".to_string();
+        return Ok(Err(BadRequest(Some(message))));
+    }
+
+    let mut tx = db.begin().await?;
+    let lines = match pick_list(&mut tx, lines, boards).await? {
+        Some(lines) => lines,
+        None => {
+            let message = "boards is too large".to_string();
+            return Ok(Err(BadRequest(Some(message))));
+        }
+    };
+    if pick {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+
+    Ok(Ok(Json(PickList {
+        boards,
+        picked: pick,
+        lines,
+    })))
+}
+
+/// Match a KiCad BOM CSV against stock and return a pick list with
+/// shortages, without taking anything out of stock.
+#[post("/bom/kicad?<boards>", data = "<data>")]
+pub async fn read_kicad(
+    db: Connection<Db>,
+    data: Data<'_>,
+    limits: &Limits,
+    boards: Option<i64>,
+) -> Result<Result<Json<PickList>, BadRequest<String>>> {
+    handle_kicad(db, data, limits, boards, false).await
+}
+
+/// Like `read_kicad`, but takes the picked parts out of stock. Lines that
+/// are short are picked as far as stock allows.
+#[post("/bom/kicad/pick?<boards>", data = "<data>")]
+pub async fn pick_kicad(
+    db: Connection<Db>,
+    data: Data<'_>,
+    limits: &Limits,
+    boards: Option<i64>,
+) -> Result<Result<Json<PickList>, BadRequest<String>>> {
+    handle_kicad(db, data, limits, boards, true).await
+}
@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rocket::data::{ByteUnit, ToByteUnit};
+use rocket::http::uri::Absolute;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+
+use crate::text::Fonts;
+use crate::AppState;
+
+/// Settings read at startup from `Rocket.toml` or `ROCKET_` environment
+/// variables, e.g. `ROCKET_ROOT_URL`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Config {
+    /// Where label codes link to, e.g. "https://inventory.example.com"
+    pub root_url: String,
+    #[serde(default)]
+    pub labels: LabelConfig,
+    #[serde(default)]
+    pub blobs: BlobConfig,
+}
+
+/// Settings shared by every label template.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LabelConfig {
+    /// The template used when none is asked for
+    pub default_template: String,
+    /// How much of a label the code takes, for templates that do not say -
+    /// see `LabelLayout::qr_size`
+    pub qr_size: Option<f32>,
+    /// A TrueType or OpenType font to print labels in, instead of the
+    /// builtin Iosevka
+    #[serde(skip_serializing)]
+    pub font: Option<PathBuf>,
+    /// Fonts for characters the main font does not have - e.g. one for CJK
+    /// and one for Arabic - tried in order
+    #[serde(skip_serializing)]
+    pub fallback_fonts: Vec<PathBuf>,
+    /// How many labels or sheets may be rendered at once - one per CPU if
+    /// unset
+    pub max_renders: Option<usize>,
+    /// How many rendered labels and sheets to keep for serving again, or 0
+    /// to keep none
+    pub cache_size: usize,
+    /// How much the kept labels and sheets may take up together, e.g.
+    /// "64 MiB" - anything bigger on its own is not kept
+    pub cache_bytes: ByteUnit,
+}
+
+impl Default for LabelConfig {
+    fn default() -> Self {
+        LabelConfig {
+            default_template: crate::template::DEFAULT_TEMPLATE.to_string(),
+            qr_size: None,
+            font: None,
+            fallback_fonts: Vec::new(),
+            max_renders: None,
+            cache_size: 256,
+            cache_bytes: 64.mebibytes(),
+        }
+    }
+}
+
+/// Where photos and attachments are kept - see `BlobStore`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BlobConfig {
+    /// The directory blobs are kept in, created if need be
+    #[serde(skip_serializing)]
+    pub path: PathBuf,
+    /// How many seconds blobs nothing refers to are kept before garbage
+    /// collection may delete them, so uploads still being saved are not lost
+    pub gc_grace: u64,
+}
+
+impl Default for BlobConfig {
+    fn default() -> Self {
+        BlobConfig {
+            path: default_blob_path(),
+            gc_grace: 3600,
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn default_blob_path() -> PathBuf {
+    PathBuf::from("blobs")
+}
+
+/// Tests keep their blobs out of the source tree, as they keep their
+/// database in memory.
+#[cfg(test)]
+fn default_blob_path() -> PathBuf {
+    std::env::temp_dir().join(format!("yvonne-test-blobs-{}", std::process::id()))
+}
+
+impl Config {
+    /// Check the settings, and drop any trailing slash from `root_url` so
+    /// paths can be appended to it.
+    pub fn validate(&mut self) -> Result<(), String> {
+        let root_url = Absolute::parse(&self.root_url)
+            .map_err(|e| format!("root_url {:?} is not a URL: {}", self.root_url, e))?;
+        if !matches!(root_url.scheme(), "http" | "https")
+            || root_url.authority().is_none()
+            || root_url.query().is_some()
+        {
+            return Err(format!(
+                "root_url {:?} must be an http or https URL with a host and no query",
+                self.root_url
+            ));
+        }
+        self.root_url = self.root_url.trim_end_matches('/').to_string();
+
+        if self.labels.max_renders == Some(0) {
+            return Err("max_renders must be at least 1".into());
+        }
+        crate::layout::validate_qr_size(self.labels.qr_size)
+    }
+}
+
+impl LabelConfig {
+    /// The fonts labels are printed in - the main font, then the fallbacks,
+    /// then the builtin font if another was chosen over it.
+    pub fn load_fonts(&self) -> Result<Fonts, String> {
+        let mut data = Vec::new();
+        for path in self.font.iter().chain(&self.fallback_fonts) {
+            let font = std::fs::read(path)
+                .map_err(|e| format!("cannot read font {}: {}", path.display(), e))?;
+            // fonts are loaded once at startup and kept until the server stops
+            let font: &'static [u8] = Box::leak(font.into_boxed_slice());
+            Fonts::new(vec![font])
+                .map_err(|_| format!("{} is not a usable font", path.display()))?;
+            data.push(font);
+        }
+        if self.font.is_some() {
+            data.push(Fonts::builtin_data());
+        } else {
+            data.insert(0, Fonts::builtin_data());
+        }
+        Fonts::new(data)
+    }
+}
+
+/// The settings the server is running with, for clients that need to know
+/// where labels link to or which template they get by default.
+#[get("/config")]
+pub fn read(state: &State<Arc<AppState>>) -> Json<Config> {
+    Json(Config {
+        root_url: state.root_url.clone(),
+        labels: state.labels.clone(),
+        blobs: state.blobs.config().clone(),
+    })
+}
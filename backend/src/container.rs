@@ -1,10 +1,14 @@
 use crate::rocket::futures::{TryFutureExt, TryStreamExt};
-use rocket::http::ContentType;
 use rocket::State;
-use rocket_db_pools::sqlx::{self, Row};
+use rocket_db_pools::sqlx::{self, Row, SqliteConnection};
 use rocket_db_pools::Connection;
-use std::io::Cursor;
+use std::sync::Arc;
 
+use crate::layout::LabelData;
+use crate::blob::StoreError;
+use crate::photo::{PhotoKind, Prepared};
+use crate::render::{Output, RenderError, Rendered};
+use crate::thermal::ThermalFormat;
 use crate::AppState;
 use crate::Db;
 
@@ -19,7 +23,6 @@ use rocket::serde::{json::Json, Deserialize, Serialize};
 
 use imageproc::drawing::draw_text;
 
-use qrcode_generator::{QRCodeError, QrCodeEcc};
 
 use rusttype::{Font, Scale};
 extern crate printpdf;
@@ -31,10 +34,8 @@ use std::convert::From;
 use std::convert::TryFrom;
 use std::fs::File;
 
-use crate::QR_CODE_DIMENSION;
 use printpdf::image_crate::GrayImage;
 use printpdf::image_crate::ImageBuffer;
-use printpdf::image_crate::ImageOutputFormat;
 use printpdf::image_crate::Luma;
 
 type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
@@ -51,8 +52,15 @@ pub struct Container {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub note: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Upload with `photo::create` instead, which does not need it spelled
+    /// out as an array of bytes. Accepted here as a new primary photo, but
+    /// left out of responses - see `photo::list`.
+    #[serde(skip_serializing)]
     pub photo: Option<Vec<u8>>,
+    /// Short code printed on labels, e.g. "C-7K2P" - see `short_code`.
+    /// Assigned by the server; ignored when given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -64,80 +72,161 @@ pub struct PutContainer {
     pub photo: Option<Vec<u8>>,
 }
 
+/// The ids and names of a container and all of its ancestors, outermost
+/// first.
+pub async fn ancestors(
+    conn: &mut SqliteConnection,
+    id: i64,
+) -> Result<Vec<(i64, String)>, sqlx::Error> {
+    // not query! - the macro cannot describe recursive queries on SQLite
+    sqlx::query_as(
+        "WITH RECURSIVE ancestor(id, parent_container_id, name, depth) AS (
+            SELECT id, parent_container_id, name, 0 FROM container WHERE id = ?
+            UNION ALL
+            SELECT container.id, container.parent_container_id, container.name, ancestor.depth + 1
+            FROM container JOIN ancestor ON container.id = ancestor.parent_container_id
+            WHERE ancestor.depth < 64
+        )
+        SELECT id, name FROM ancestor ORDER BY depth DESC",
+    )
+    .bind(id)
+    .fetch(conn)
+    .try_collect()
+    .await
+}
+
+/// The names of a container and all of its ancestors, outermost first - e.g.
+/// `["1000 Washington Street", "Toolchest", "Top Drawer"]`.
+pub async fn path(conn: &mut SqliteConnection, id: i64) -> Result<Vec<String>, sqlx::Error> {
+    let ancestors = ancestors(conn, id).await?;
+    Ok(ancestors.into_iter().map(|(_, name)| name).collect())
+}
+
 #[post("/container", data = "<container>")]
 pub async fn create(
     mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
     container: Json<Container>,
-) -> Result<Created<Json<Container>>> {
-    sqlx::query!(
-        "INSERT INTO container (parent_container_id, name, note, photo) VALUES (?, ?, ?, ?)",
+) -> Result<Created<Json<Container>>, StoreError> {
+    let result = sqlx::query!(
+        "INSERT INTO container (parent_container_id, name, note) VALUES (?, ?, ?)",
         container.parent_container_id,
         container.name,
-        container.note,
-        container.photo
+        container.note
     )
     .execute(&mut *db)
     .await?;
+    let id = result.last_insert_rowid();
+    if let Some(photo) = &container.photo {
+        let photo = Prepared::raw(photo.clone());
+        crate::photo::insert(&mut db, &state.blobs, PhotoKind::Container, id, photo, None, true)
+            .await?;
+    }
+    crate::short_code::assign(&mut db, "container", id).await?;
 
     Ok(Created::new("/").body(container))
 }
 
-#[get("/container/<id>")]
-pub async fn read(mut db: Connection<Db>, id: i64) -> Option<Json<Container>> {
-    sqlx::query!(
-        "SELECT id, parent_container_id, name, note, photo FROM container WHERE id = ?",
+/// A container by id, with its short code.
+pub async fn fetch(db: &mut Connection<Db>, id: i64) -> Option<Container> {
+    let mut container = sqlx::query!(
+        "SELECT id, parent_container_id, name, note FROM container WHERE id = ?",
         id
     )
-    .fetch_one(&mut *db)
-    .map_ok(|r| {
-        Json(Container {
-            id: Some(r.id),
-            parent_container_id: r.parent_container_id,
-            name: r.name,
-            note: r.note,
-            photo: r.photo,
-        })
+    .fetch_one(&mut **db)
+    .map_ok(|r| Container {
+        id: Some(r.id),
+        parent_container_id: r.parent_container_id,
+        name: r.name,
+        note: r.note,
+        photo: None,
+        code: None,
     })
     .await
-    .ok()
+    .ok()?;
+    container.code = crate::short_code::get(db, "container", id).await.ok()?;
+    Some(container)
 }
 
-#[get("/container/qr/<id>")]
+#[get("/container/<id>", rank = 2)]
+pub async fn read(mut db: Connection<Db>, id: i64) -> Option<Json<Container>> {
+    fetch(&mut db, id).await.map(Json)
+}
+
+/// What goes on a container's label.
+pub async fn label_data(conn: &mut SqliteConnection, id: i64) -> Result<LabelData, sqlx::Error> {
+    let container = sqlx::query!(
+        "SELECT id, parent_container_id, name FROM container WHERE id = ?",
+        id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let mut label = LabelData::new(container.id, "container", container.name);
+    label.code = crate::short_code::get(&mut *conn, "container", id).await?;
+    if let Some(parent_id) = container.parent_container_id {
+        label.path = Some(path(&mut *conn, parent_id).await?.join(" / "));
+    }
+    Ok(label)
+}
+
+/// A label as an SVG drawing, for printing at any size without blurring.
+#[get("/container/svg/<id>?<template>")]
+pub async fn read_svg(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    crate::render::label(state, label_data(&mut db, id).await, template, Output::Svg).await
+}
+
+/// A label for a thermal printer, as ZPL or Brother QL raster.
+#[get("/container/thermal/<id>?<format>&<template>")]
+pub async fn read_thermal(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    format: ThermalFormat,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    let output = Output::Thermal(format);
+    crate::render::label(state, label_data(&mut db, id).await, template, output).await
+}
+
+#[get("/container/qr/<id>?<template>")]
 pub async fn read_qr(
     mut db: Connection<Db>,
-    state: &State<AppState>,
+    state: &State<Arc<AppState>>,
     id: i64,
-) -> (ContentType, Vec<u8>) {
-    let foo = sqlx::query!("SELECT id, name FROM container WHERE id = ?", id)
-        .fetch_one(&mut *db)
-        .map_ok(|r| {
-            //generate_container_qr_label(state, r.id, r.name)
-            crate::util::generate_qr_label(state, r.id, r.name, "container")
-        })
-        .await
-        .expect("Got image okay");
-    let mut bytes: Vec<u8> = Vec::new();
-    foo.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
-        .expect("Saved as PNG okay");
-    (ContentType::PNG, bytes)
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    crate::render::label(state, label_data(&mut db, id).await, template, Output::Png).await
 }
 
+/// Replace a container. A photo given here is added as its primary photo,
+/// and its other photos are left as they are.
 #[put("/container/<id>", data = "<container>")]
 pub async fn full_update(
     mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
     id: i64,
     container: Json<PutContainer>,
-) -> Result<Created<Json<Container>>> {
+) -> Result<Created<Json<Container>>, StoreError> {
     sqlx::query!(
-        "UPDATE container SET parent_container_id=?, name=?, note=?, photo=? WHERE id = ?",
+        "UPDATE container SET parent_container_id = ?, name = ?, note = ? WHERE id = ?",
         container.parent_container_id,
         container.name,
         container.note,
-        container.photo,
         id
     )
     .execute(&mut *db)
     .await?;
+    if let Some(photo) = &container.photo {
+        let photo = Prepared::raw(photo.clone());
+        crate::photo::insert(&mut db, &state.blobs, PhotoKind::Container, id, photo, None, true)
+            .await?;
+    }
 
     Ok(Created::new("/")) // TODO revisit this return
 }
@@ -162,16 +251,24 @@ pub async fn list(mut db: Connection<Db>) -> Result<Json<Vec<i64>>> {
     Ok(Json(ids))
 }
 
-#[get("/container/qr")]
-pub async fn list_qr(state: &State<AppState>, mut db: Connection<Db>) -> (ContentType, Vec<u8>) {
-    let containers = sqlx::query!("SELECT id, name FROM container")
+#[get("/container/qr?<template>")]
+pub async fn list_qr(
+    state: &State<Arc<AppState>>,
+    mut db: Connection<Db>,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    let template = match crate::template::find(state, template) {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let ids = sqlx::query!("SELECT id FROM container")
         .fetch(&mut *db)
-        .map_ok(|r| (r.id.unwrap(), r.name))
+        .map_ok(|r| r.id.unwrap())
         .try_collect::<Vec<_>>()
-        .await
-        .unwrap();
-    (
-        ContentType::PDF,
-        crate::util::generate_qr_pdf(state, containers, "container"),
-    )
+        .await?;
+    let mut containers = Vec::new();
+    for id in ids {
+        containers.push(label_data(&mut db, id).await?);
+    }
+    Ok(Some(crate::render::labels(state, containers, template, Output::Pdf).await))
 }
@@ -1,11 +1,15 @@
 use crate::rocket::futures::{TryFutureExt, TryStreamExt};
-use std::io::Cursor;
-use rocket::http::ContentType;
-use rocket_db_pools::sqlx::{self, Row};
+use std::sync::Arc;
+use rocket_db_pools::sqlx::{self, Row, SqliteConnection};
 use rocket_db_pools::Connection;
 use rocket::State;
 
 use crate::Db;
+use crate::layout::LabelData;
+use crate::blob::StoreError;
+use crate::photo::{PhotoKind, Prepared};
+use crate::render::{Output, RenderError, Rendered};
+use crate::thermal::ThermalFormat;
 use crate::AppState;
 
 use lazy_static::lazy_static;
@@ -19,7 +23,6 @@ use rocket::serde::{json::Json, Deserialize, Serialize};
 
 use imageproc::drawing::draw_text;
 
-use qrcode_generator::{QrCodeEcc, QRCodeError};
 
 use rusttype::{Font, Scale};
 extern crate printpdf;
@@ -31,11 +34,9 @@ use std::convert::From;
 use std::convert::TryFrom;
 use std::fs::File;
 
-use printpdf::image_crate::ImageOutputFormat;
 use printpdf::image_crate::Luma;
 use printpdf::image_crate::ImageBuffer;
 use printpdf::image_crate::{GrayImage};
-use crate::QR_CODE_DIMENSION;
 
 type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
 
@@ -48,8 +49,24 @@ pub struct Item {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub note: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Upload with `photo::create` instead, which does not need it spelled
+    /// out as an array of bytes. Accepted here as a new primary photo, but
+    /// left out of responses - see `photo::list`.
+    #[serde(skip_serializing)]
     pub photo: Option<Vec<u8>>,
+    /// Manufacturer part number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpn: Option<String>,
+    /// Component value as it appears in a schematic - e.g. "10k" or "100nF"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// KiCad footprint - e.g. "Resistor_SMD:R_0603_1608Metric"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footprint: Option<String>,
+    /// Short code printed on labels, e.g. "I-7K2P" - see `short_code`.
+    /// Assigned by the server; ignored when given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
 }
 
 /// An item type - not an individual item. e.g. M3 bolt, 20mm long
@@ -59,69 +76,148 @@ pub struct PutItem {
     pub name: String,
     pub note: Option<String>,
     pub photo: Option<Vec<u8>>,
+    pub mpn: Option<String>,
+    pub value: Option<String>,
+    pub footprint: Option<String>,
 }
 
 #[post("/item", data = "<item>")]
-pub async fn create(mut db: Connection<Db>, item: Json<Item>) -> Result<Created<Json<Item>>> {
-    sqlx::query!(
-        "INSERT INTO item (name, note, photo) VALUES (?, ?, ?)",
+pub async fn create(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    item: Json<Item>,
+) -> Result<Created<Json<Item>>, StoreError> {
+    let result = sqlx::query!(
+        "INSERT INTO item (name, note, mpn, value, footprint) VALUES (?, ?, ?, ?, ?)",
         item.name,
         item.note,
-        item.photo
+        item.mpn,
+        item.value,
+        item.footprint
     )
     .execute(&mut *db)
     .await?;
+    let id = result.last_insert_rowid();
+    if let Some(photo) = &item.photo {
+        let photo = Prepared::raw(photo.clone());
+        crate::photo::insert(&mut db, &state.blobs, PhotoKind::Item, id, photo, None, true)
+            .await?;
+    }
+    crate::short_code::assign(&mut db, "item", id).await?;
 
     Ok(Created::new("/").body(item))
 }
 
-#[get("/item/<id>")]
+/// An item by id, with its short code.
+pub async fn fetch(db: &mut Connection<Db>, id: i64) -> Option<Item> {
+    let mut item = sqlx::query!(
+        "SELECT id, name, note, mpn, value, footprint FROM item WHERE id = ?",
+        id
+    )
+    .fetch_one(&mut **db)
+    .map_ok(|r| Item {
+        id: Some(r.id),
+        name: r.name,
+        note: r.note,
+        photo: None,
+        mpn: r.mpn,
+        value: r.value,
+        footprint: r.footprint,
+        code: None,
+    })
+    .await
+    .ok()?;
+    item.code = crate::short_code::get(db, "item", id).await.ok()?;
+    Some(item)
+}
+
+#[get("/item/<id>", rank = 2)]
 pub async fn read(mut db: Connection<Db>, id: i64) -> Option<Json<Item>> {
-    sqlx::query!("SELECT id,name, note, photo FROM item WHERE id = ?", id)
-        .fetch_one(&mut *db)
-        .map_ok(|r| {
-            Json(Item {
-                id: Some(r.id),
-                name: r.name,
-                note: r.note,
-                photo: r.photo,
-            })
-        })
-        .await
-        .ok()
+    fetch(&mut db, id).await.map(Json)
 }
 
-#[get("/item/qr/<id>")]
-pub async fn read_qr(mut db: Connection<Db>, state: &State<AppState>, id:i64) -> (ContentType, Vec<u8>) {
-    let foo = sqlx::query!(
-        "SELECT id, name FROM item WHERE id = ?",
+/// What goes on an item's label - where it is kept first, and how many there
+/// are altogether.
+pub async fn label_data(conn: &mut SqliteConnection, id: i64) -> Result<LabelData, sqlx::Error> {
+    let item = sqlx::query!("SELECT id, name FROM item WHERE id = ?", id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let mut label = LabelData::new(item.id, "item", item.name);
+    label.code = crate::short_code::get(&mut *conn, "item", id).await?;
+    let location = sqlx::query!(
+        "SELECT container_id FROM item_location WHERE item_id = ? ORDER BY id",
         id
     )
-    .fetch_one(&mut *db)
-    .map_ok(|r| {
-        //generate_item_qr_label(state, r.id, r.name)
-        crate::util::generate_qr_label(state, r.id, r.name, "item")
-    }).await.expect("Got image okay");
-    let mut bytes: Vec<u8> = Vec::new();
-    foo.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png).expect("Saved as PNG okay");
-    (ContentType::PNG, bytes)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(location) = location {
+        let path = crate::container::path(&mut *conn, location.container_id).await?;
+        label.path = Some(path.join(" / "));
+    }
+    label.quantity = Some(crate::item_location::stock(&mut *conn, id).await?);
+    Ok(label)
+}
+
+/// A label as an SVG drawing, for printing at any size without blurring.
+#[get("/item/svg/<id>?<template>")]
+pub async fn read_svg(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    crate::render::label(state, label_data(&mut db, id).await, template, Output::Svg).await
+}
+
+/// A label for a thermal printer, as ZPL or Brother QL raster.
+#[get("/item/thermal/<id>?<format>&<template>")]
+pub async fn read_thermal(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    format: ThermalFormat,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    let output = Output::Thermal(format);
+    crate::render::label(state, label_data(&mut db, id).await, template, output).await
 }
 
+#[get("/item/qr/<id>?<template>")]
+pub async fn read_qr(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    crate::render::label(state, label_data(&mut db, id).await, template, Output::Png).await
+}
+
+/// Replace an item. A photo given here is added as its primary photo, and
+/// its other photos are left as they are.
 #[put("/item/<id>", data = "<item>")]
 pub async fn full_update(
     mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
     id: i64,
     item: Json<PutItem>,
-) -> Result<Created<Json<Item>>> {
+) -> Result<Created<Json<Item>>, StoreError> {
     sqlx::query!(
-        "UPDATE item SET name=?, note=?, photo=? WHERE id = ?",
+        "UPDATE item SET name = ?, note = ?, mpn = ?, value = ?, footprint = ? WHERE id = ?",
         item.name,
         item.note,
-        item.photo,
+        item.mpn,
+        item.value,
+        item.footprint,
         id
     )
     .execute(&mut *db)
     .await?;
+    if let Some(photo) = &item.photo {
+        let photo = Prepared::raw(photo.clone());
+        crate::photo::insert(&mut db, &state.blobs, PhotoKind::Item, id, photo, None, true)
+            .await?;
+    }
 
     Ok(Created::new("/")) // TODO revisit this return
 }
@@ -146,12 +242,24 @@ pub async fn list(mut db: Connection<Db>) -> Result<Json<Vec<i64>>> {
     Ok(Json(ids))
 }
 
-#[get("/item/qr")]
-pub async fn list_qr(state: &State<AppState>, mut db: Connection<Db>) -> (ContentType, Vec<u8>) {
-    let items = sqlx::query!("SELECT id, name FROM item")
+#[get("/item/qr?<template>")]
+pub async fn list_qr(
+    state: &State<Arc<AppState>>,
+    mut db: Connection<Db>,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    let template = match crate::template::find(state, template) {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let ids = sqlx::query!("SELECT id FROM item")
         .fetch(&mut *db)
-        .map_ok(|r| (r.id.unwrap(), r.name))
+        .map_ok(|r| r.id.unwrap())
         .try_collect::<Vec<_>>()
-        .await.unwrap();
-    (ContentType::PDF, crate::util::generate_qr_pdf(state, items, "item"))
+        .await?;
+    let mut items = Vec::new();
+    for id in ids {
+        items.push(label_data(&mut db, id).await?);
+    }
+    Ok(Some(crate::render::labels(state, items, template, Output::Pdf).await))
 }
@@ -1,9 +1,11 @@
-use crate::rocket::futures::TryFutureExt;
-use rocket_db_pools::sqlx::{self, Row};
+use crate::rocket::futures::{TryFutureExt, TryStreamExt};
+use rocket_db_pools::sqlx::{self, Acquire, Row, SqliteConnection};
 use rocket_db_pools::Connection;
 
 use crate::Db;
-use rocket::response::status::Created;
+use rocket::form::Form;
+use rocket::response::status::{BadRequest, Conflict, Created};
+use rocket::response::Redirect;
 use rocket::serde::{json::Json, Deserialize, Serialize};
 
 type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
@@ -17,6 +19,140 @@ pub struct ItemLocation {
     pub item_id: i64,
     pub container_id: i64,
     pub quantity: Option<i64>,
+    /// Manufacturer lot or batch number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lot: Option<String>,
+    /// ISO 8601 date, e.g. "2023-06-30"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_on: Option<String>,
+}
+
+/// Part of a stock withdrawal - take `quantity` from item location `item_location_id`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Pick {
+    pub item_location_id: i64,
+    pub item_id: i64,
+    pub container_id: i64,
+    pub quantity: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PostConsume {
+    pub item_id: i64,
+    pub quantity: i64,
+}
+
+/// A change in quantity from the buttons on the HTML pages, and the page to
+/// go back to afterwards.
+#[derive(Debug, FromForm)]
+pub struct Adjust<'r> {
+    pub delta: i64,
+    pub back: &'r str,
+}
+
+/// Total quantity of an item across all of its locations. Locations without a
+/// quantity are not counted, nor are lots past their expiry date, as
+/// `plan_picks` never takes from them.
+pub async fn stock(conn: &mut SqliteConnection, item_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        r#"SELECT COALESCE(SUM(quantity), 0) AS "stock!: i64" FROM item_location
+        WHERE item_id = ? AND (expires_on IS NULL OR expires_on >= date('now'))"#,
+        item_id
+    )
+    .fetch_one(conn)
+    .map_ok(|r| r.stock)
+    .await
+}
+
+/// Work out where to take `quantity` of an item from, without touching stock.
+/// Lots are picked first-expired-first-out, then in the order they were
+/// stocked. Lots past their expiry date are never picked - see
+/// `list_expiring` for those. Returns the picks and the quantity that could
+/// not be covered.
+pub async fn plan_picks(
+    conn: &mut SqliteConnection,
+    item_id: i64,
+    quantity: i64,
+) -> Result<(Vec<Pick>, i64), sqlx::Error> {
+    let locations = sqlx::query!(
+        r#"SELECT id AS "id!", container_id, quantity AS "quantity!" FROM item_location
+        WHERE item_id = ? AND quantity > 0 AND (expires_on IS NULL OR expires_on >= date('now'))
+        ORDER BY expires_on IS NULL, expires_on, id"#,
+        item_id
+    )
+    .fetch(conn)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let mut picks = Vec::new();
+    let mut remaining = quantity;
+    for location in locations {
+        if remaining <= 0 {
+            break;
+        }
+        let taken = remaining.min(location.quantity);
+        picks.push(Pick {
+            item_location_id: location.id,
+            item_id,
+            container_id: location.container_id,
+            quantity: taken,
+        });
+        remaining -= taken;
+    }
+
+    Ok((picks, remaining.max(0)))
+}
+
+/// Take planned picks out of stock. Should run in the same transaction as the
+/// `plan_picks` call that produced them.
+pub async fn apply_picks(conn: &mut SqliteConnection, picks: &[Pick]) -> Result<(), sqlx::Error> {
+    for pick in picks {
+        sqlx::query!(
+            "UPDATE item_location SET quantity = quantity - ? WHERE id = ?",
+            pick.quantity,
+            pick.item_location_id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Put `quantity` of an item into a container, topping up an existing item
+/// location there if there is one. Locations holding a specific lot are
+/// left alone.
+pub async fn add_stock(
+    conn: &mut SqliteConnection,
+    item_id: i64,
+    container_id: i64,
+    quantity: i64,
+) -> Result<(), sqlx::Error> {
+    let updated = sqlx::query!(
+        "UPDATE item_location SET quantity = COALESCE(quantity, 0) + ?
+        WHERE id = (SELECT MIN(id) FROM item_location WHERE item_id = ? AND container_id = ?
+            AND lot IS NULL AND expires_on IS NULL)",
+        quantity,
+        item_id,
+        container_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        sqlx::query!(
+            "INSERT INTO item_location (item_id, container_id, quantity) VALUES (?, ?, ?)",
+            item_id,
+            container_id,
+            quantity
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
 }
 
 /// Create a name item location.
@@ -26,10 +162,13 @@ pub async fn create(
     itemloc: Json<ItemLocation>,
 ) -> Result<Created<Json<ItemLocation>>> {
     sqlx::query!(
-        "INSERT INTO item_location (item_id, container_id, quantity) VALUES (?, ?, ?)",
+        "INSERT INTO item_location (item_id, container_id, quantity, lot, expires_on)
+        VALUES (?, ?, ?, ?, ?)",
         itemloc.item_id,
         itemloc.container_id,
         itemloc.quantity,
+        itemloc.lot,
+        itemloc.expires_on,
     )
     .execute(&mut *db)
     .await?;
@@ -40,7 +179,7 @@ pub async fn create(
 #[get("/itemloc/<id>")]
 pub async fn read(mut db: Connection<Db>, id: i64) -> Option<Json<ItemLocation>> {
     sqlx::query!(
-        "SELECT id, item_id, container_id, quantity FROM item_location WHERE id = ?",
+        "SELECT id, item_id, container_id, quantity, lot, expires_on FROM item_location WHERE id = ?",
         id
     )
     .fetch_one(&mut *db)
@@ -50,6 +189,8 @@ pub async fn read(mut db: Connection<Db>, id: i64) -> Option<Json<ItemLocation>>
             item_id: r.item_id,
             container_id: r.container_id,
             quantity: r.quantity,
+            lot: r.lot,
+            expires_on: r.expires_on,
         })
     })
     .await
@@ -64,3 +205,85 @@ pub async fn delete(mut db: Connection<Db>, id: i64) -> Result<Option<()>> {
 
     Ok((result.rows_affected() == 1).then(|| ()))
 }
+
+/// Stock that has expired or will expire within `days` days, soonest first.
+/// Empty locations are left out.
+#[get("/itemloc/expiring?<days>")]
+pub async fn list_expiring(
+    mut db: Connection<Db>,
+    days: Option<i64>,
+) -> Result<Result<Json<Vec<ItemLocation>>, BadRequest<String>>> {
+    let days = days.unwrap_or(30);
+    if days < 0 {
+        return Ok(Err(BadRequest(Some(
+            "days must not be negative".to_string(),
+        ))));
+    }
+    let modifier = format!("+{} days", days);
+    let itemlocs = sqlx::query!(
+        r#"SELECT id AS "id!", item_id, container_id, quantity, lot, expires_on FROM item_location
+        WHERE expires_on <= date('now', ?) AND (quantity IS NULL OR quantity > 0)
+        ORDER BY expires_on, id"#,
+        modifier
+    )
+    .fetch(&mut *db)
+    .map_ok(|r| ItemLocation {
+        id: Some(r.id),
+        item_id: r.item_id,
+        container_id: r.container_id,
+        quantity: r.quantity,
+        lot: r.lot,
+        expires_on: r.expires_on,
+    })
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(Ok(Json(itemlocs)))
+}
+
+/// Take some of an item out of stock, earliest-expiring lots first. Nothing
+/// is taken unless there is enough.
+#[post("/itemloc/consume", data = "<consume>")]
+pub async fn consume(
+    mut db: Connection<Db>,
+    consume: Json<PostConsume>,
+) -> Result<Result<Json<Vec<Pick>>, Conflict<Json<i64>>>> {
+    let mut tx = db.begin().await?;
+
+    let (picks, shortage) = plan_picks(&mut tx, consume.item_id, consume.quantity).await?;
+    if shortage > 0 {
+        return Ok(Err(Conflict(Some(Json(shortage)))));
+    }
+    apply_picks(&mut tx, &picks).await?;
+
+    tx.commit().await?;
+
+    Ok(Ok(Json(picks)))
+}
+
+/// Put some of an item back or take some out from the HTML pages, never
+/// going below none, then go back to the page.
+#[post("/itemloc/<id>/adjust", data = "<adjust>")]
+pub async fn adjust(
+    mut db: Connection<Db>,
+    id: i64,
+    adjust: Form<Adjust<'_>>,
+) -> Result<Option<Redirect>> {
+    let result = sqlx::query!(
+        "UPDATE item_location SET quantity = MAX(COALESCE(quantity, 0) + ?, 0) WHERE id = ?",
+        adjust.delta,
+        id
+    )
+    .execute(&mut *db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    // only ever back to one of our own pages
+    let back = match adjust.back {
+        back if back.starts_with('/') && !back.starts_with("//") && !back.contains('\\') => back,
+        _ => "/",
+    };
+    Ok(Some(Redirect::to(back.to_string())))
+}
@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+
+use crate::rocket::futures::TryStreamExt;
+use rocket_db_pools::sqlx::{self, Acquire, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::item_location::{self, Pick};
+use crate::Db;
+use rocket::response::status::{BadRequest, Created};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// Turned away when a quantity asks for more components than can be counted.
+const TOO_MANY: &str = "quantity is too large";
+
+/// One line of a kit's bill of materials - e.g. the "Arduino starter kit" item
+/// needs 10 of the "220R resistor" item.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct KitComponent {
+    pub id: i64,
+    pub kit_item_id: i64,
+    pub component_item_id: i64,
+    pub quantity: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PostKitComponent {
+    pub component_item_id: i64,
+    pub quantity: i64,
+}
+
+/// Stock of one component measured against what `quantity` kits need.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ComponentAvailability {
+    pub component_item_id: i64,
+    pub name: String,
+    pub required: i64,
+    pub in_stock: i64,
+    pub missing: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct KitAvailability {
+    pub kit_item_id: i64,
+    /// How many kits current stock is enough for
+    pub buildable: i64,
+    /// The number of kits the components were checked against
+    pub quantity: i64,
+    pub components: Vec<ComponentAvailability>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PostKitBuild {
+    pub container_id: i64,
+    pub quantity: Option<i64>,
+}
+
+/// The result of assembling kits - which component stock was consumed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct KitBuild {
+    pub kit_item_id: i64,
+    pub container_id: i64,
+    pub quantity: i64,
+    pub picks: Vec<Pick>,
+}
+
+/// Why kits could not be built.
+#[derive(Debug, Responder)]
+pub enum BuildError {
+    /// Asked for less than one kit, or too many to count
+    #[response(status = 400)]
+    Invalid(String),
+    /// Not every component is in stock
+    #[response(status = 409)]
+    Unavailable(Json<KitAvailability>),
+}
+
+/// Add a component to a kit. Kits need at least one of each component, and
+/// cannot be a component of themselves - not even through other kits.
+#[post("/kit/<kit_item_id>/component", data = "<component>")]
+pub async fn create(
+    mut db: Connection<Db>,
+    kit_item_id: i64,
+    component: Json<PostKitComponent>,
+) -> Result<Result<Created<Json<KitComponent>>, BadRequest<String>>> {
+    if component.quantity < 1 {
+        let message = "component quantity must be at least 1".to_string();
+        return Ok(Err(BadRequest(Some(message))));
+    }
+    if goes_into(&mut db, kit_item_id, component.component_item_id).await? {
+        let message = "a kit cannot be a component of itself".to_string();
+        return Ok(Err(BadRequest(Some(message))));
+    }
+    let id = sqlx::query!(
+        "INSERT INTO kit_component (kit_item_id, component_item_id, quantity) VALUES (?, ?, ?)",
+        kit_item_id,
+        component.component_item_id,
+        component.quantity
+    )
+    .execute(&mut *db)
+    .await?
+    .last_insert_rowid();
+
+    Ok(Ok(Created::new("/").body(Json(KitComponent {
+        id,
+        kit_item_id,
+        component_item_id: component.component_item_id,
+        quantity: component.quantity,
+    }))))
+}
+
+#[get("/kit/<kit_item_id>/component")]
+pub async fn list(mut db: Connection<Db>, kit_item_id: i64) -> Result<Json<Vec<KitComponent>>> {
+    let components = sqlx::query!(
+        "SELECT id, kit_item_id, component_item_id, quantity FROM kit_component WHERE kit_item_id = ?",
+        kit_item_id
+    )
+    .fetch(&mut *db)
+    .map_ok(|r| KitComponent {
+        id: r.id.unwrap(),
+        kit_item_id: r.kit_item_id,
+        component_item_id: r.component_item_id,
+        quantity: r.quantity,
+    })
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(Json(components))
+}
+
+#[delete("/kit/component/<id>")]
+pub async fn delete(mut db: Connection<Db>, id: i64) -> Result<Option<()>> {
+    let result = sqlx::query!("DELETE FROM kit_component WHERE id = ?", id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+/// Whether `part_id` is `item_id` or one of its components, at any depth.
+async fn goes_into(
+    conn: &mut SqliteConnection,
+    part_id: i64,
+    item_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![item_id];
+    while let Some(id) = pending.pop() {
+        if id == part_id {
+            return Ok(true);
+        }
+        if !seen.insert(id) {
+            continue;
+        }
+        for r in sqlx::query!(
+            "SELECT component_item_id FROM kit_component WHERE kit_item_id = ?",
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        {
+            pending.push(r.component_item_id);
+        }
+    }
+    Ok(false)
+}
+
+/// Stock measured against `quantity` kits, or `None` if the number of some
+/// component they need does not fit in an `i64`.
+async fn availability(
+    conn: &mut SqliteConnection,
+    kit_item_id: i64,
+    quantity: i64,
+) -> Result<Option<KitAvailability>, sqlx::Error> {
+    let components = sqlx::query!(
+        r#"SELECT kit_component.component_item_id, kit_component.quantity, item.name
+        FROM kit_component JOIN item ON item.id = kit_component.component_item_id
+        WHERE kit_component.kit_item_id = ?"#,
+        kit_item_id
+    )
+    .fetch(&mut *conn)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let mut buildable = if components.is_empty() { 0 } else { i64::MAX };
+    let mut availability = Vec::new();
+    for component in components {
+        let in_stock = item_location::stock(&mut *conn, component.component_item_id).await?;
+        let required = match component.quantity.checked_mul(quantity) {
+            Some(required) => required,
+            None => return Ok(None),
+        };
+        buildable = buildable.min(in_stock / component.quantity);
+        availability.push(ComponentAvailability {
+            component_item_id: component.component_item_id,
+            name: component.name,
+            required,
+            in_stock,
+            missing: (required - in_stock).max(0),
+        });
+    }
+
+    Ok(Some(KitAvailability {
+        kit_item_id,
+        buildable,
+        quantity,
+        components: availability,
+    }))
+}
+
+/// How many kits can be built from stock, and what is missing to build
+/// `quantity` of them.
+#[get("/kit/<kit_item_id>/availability?<quantity>")]
+pub async fn read_availability(
+    mut db: Connection<Db>,
+    kit_item_id: i64,
+    quantity: Option<i64>,
+) -> Result<Result<Json<KitAvailability>, BadRequest<String>>> {
+    let quantity = quantity.unwrap_or(1);
+    if quantity < 1 {
+        let message = "quantity must be at least 1".to_string();
+        return Ok(Err(BadRequest(Some(message))));
+    }
+    match availability(&mut db, kit_item_id, quantity).await? {
+        Some(availability) => Ok(Ok(Json(availability))),
+        None => Ok(Err(BadRequest(Some(TOO_MANY.to_string())))),
+    }
+}
+
+/// Assemble kits - consume their components from stock and put the
+/// assembled kits into a container. Nothing is consumed unless every
+/// component is available - expired lots do not count.
+#[post("/kit/<kit_item_id>/build", data = "<build>")]
+pub async fn build(
+    mut db: Connection<Db>,
+    kit_item_id: i64,
+    build: Json<PostKitBuild>,
+) -> Result<Result<Json<KitBuild>, BuildError>> {
+    let quantity = build.quantity.unwrap_or(1);
+    if quantity < 1 {
+        let message = "quantity must be at least 1".to_string();
+        return Ok(Err(BuildError::Invalid(message)));
+    }
+    let mut tx = db.begin().await?;
+
+    let availability = match availability(&mut tx, kit_item_id, quantity).await? {
+        Some(availability) => availability,
+        None => return Ok(Err(BuildError::Invalid(TOO_MANY.to_string()))),
+    };
+    if availability.components.is_empty() || availability.components.iter().any(|c| c.missing > 0) {
+        return Ok(Err(BuildError::Unavailable(Json(availability))));
+    }
+
+    let mut picks = Vec::new();
+    for component in &availability.components {
+        let (component_picks, shortage) =
+            item_location::plan_picks(&mut tx, component.component_item_id, component.required)
+                .await?;
+        if shortage > 0 {
+            // in stock, but expired
+            return Ok(Err(BuildError::Unavailable(Json(availability))));
+        }
+        item_location::apply_picks(&mut tx, &component_picks).await?;
+        picks.extend(component_picks);
+    }
+    item_location::add_stock(&mut tx, kit_item_id, build.container_id, quantity).await?;
+
+    tx.commit().await?;
+
+    Ok(Ok(Json(KitBuild {
+        kit_item_id,
+        container_id: build.container_id,
+        quantity,
+        picks,
+    })))
+}
@@ -0,0 +1,410 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::rocket::futures::TryStreamExt;
+use rocket::http::ContentType;
+use rocket::State;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::layout::LabelData;
+use crate::render::RenderError;
+use crate::symbol::{EcLevel, Symbology};
+use crate::template::LabelTemplate;
+use crate::thermal::ThermalFormat;
+use crate::AppState;
+use crate::Db;
+
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// The kinds of thing that get labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum LabelKind {
+    Container,
+    Item,
+    Unit,
+}
+
+impl LabelKind {
+    /// The route the kind's QR payloads and endpoints live under.
+    pub fn route(&self) -> &'static str {
+        match self {
+            LabelKind::Container => "container",
+            LabelKind::Item => "item",
+            LabelKind::Unit => "unit",
+        }
+    }
+}
+
+/// One container, item or unit to print a label for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LabelRef {
+    pub kind: LabelKind,
+    pub id: i64,
+}
+
+/// Which labels to select besides the explicitly listed ones.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LabelFilter {
+    /// Kinds to select - all of them if empty
+    pub kinds: Vec<LabelKind>,
+    /// Only select names containing this, ignoring case
+    pub name: Option<String>,
+}
+
+/// A request for a sheet of labels. The explicitly listed `ids` are printed
+/// first, in order, followed by whatever `subtree` and `filter` select -
+/// containers, then items, then units. Nothing is printed twice.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct PostLabels {
+    pub ids: Vec<LabelRef>,
+    /// Select a container, everything inside it, and the items and units
+    /// kept in any of those
+    pub subtree: Option<i64>,
+    pub filter: Option<LabelFilter>,
+    /// Label slots to leave empty at the start of the first sheet, so a
+    /// partly used sheet can be fed back in
+    pub offset: usize,
+    pub template: Option<String>,
+    /// Print for a thermal printer instead of onto a PDF sheet
+    pub format: Option<ThermalFormat>,
+    /// Override the template's symbology and error correction
+    pub symbology: Option<Symbology>,
+    pub ec_level: Option<EcLevel>,
+}
+
+async fn label_data(
+    conn: &mut SqliteConnection,
+    label: LabelRef,
+) -> Result<Option<LabelData>, sqlx::Error> {
+    let data = match label.kind {
+        LabelKind::Container => crate::container::label_data(conn, label.id).await,
+        LabelKind::Item => crate::item::label_data(conn, label.id).await,
+        LabelKind::Unit => crate::unit::label_data(conn, label.id).await,
+    };
+    match data {
+        Ok(data) => Ok(Some(data)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Ids of one kind of entity matching a subtree and name filter. Either may
+/// be `None` to not filter on it.
+async fn select(
+    conn: &mut SqliteConnection,
+    kind: LabelKind,
+    subtree: Option<i64>,
+    name: Option<&str>,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let selected = match kind {
+        LabelKind::Container => {
+            "SELECT container.id FROM container JOIN subtree USING (id)
+            WHERE instr(lower(container.name), lower(?2)) OR ?2 IS NULL"
+        }
+        LabelKind::Item => {
+            "SELECT item.id FROM item
+            WHERE (?1 IS NULL OR item.id IN (
+                SELECT item_id FROM item_location JOIN subtree ON subtree.id = container_id
+            ))
+            AND (instr(lower(item.name), lower(?2)) OR ?2 IS NULL)"
+        }
+        LabelKind::Unit => {
+            "SELECT unit.id FROM unit JOIN item ON item.id = unit.item_id
+            WHERE (?1 IS NULL OR unit.container_id IN (SELECT id FROM subtree))
+            AND (instr(lower(item.name || ' ' || unit.serial_number), lower(?2)) OR ?2 IS NULL)"
+        }
+    };
+    let sql = format!(
+        "WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM container WHERE ?1 IS NULL OR id = ?1
+            UNION
+            SELECT container.id FROM container JOIN subtree ON container.parent_container_id = subtree.id
+        )
+        {} ORDER BY 1",
+        selected
+    );
+    // not query! - the macro cannot describe recursive queries on SQLite
+    sqlx::query_scalar::<_, i64>(&sql)
+        .bind(subtree)
+        .bind(name)
+        .fetch(conn)
+        .try_collect()
+        .await
+}
+
+/// Why a label needs printing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum PendingReason {
+    /// No label has been printed for the entity
+    Unprinted,
+    /// The printed label's code or text no longer match the entity - e.g.
+    /// it has been renamed or moved
+    Outdated,
+}
+
+/// An entity whose label needs printing.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PendingLabel {
+    pub kind: LabelKind,
+    pub id: i64,
+    pub name: String,
+    pub reason: PendingReason,
+    /// When the outdated label was printed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub printed_at: Option<String>,
+}
+
+/// A template by name, falling back to the default, with its symbology and
+/// error correction overridden.
+fn template_for(
+    state: &AppState,
+    name: Option<&str>,
+    symbology: Option<Symbology>,
+    ec_level: Option<EcLevel>,
+) -> Result<LabelTemplate, String> {
+    let mut template = crate::template::find(state, name)
+        .ok_or("unknown template")?
+        .clone();
+    if let Some(symbology) = symbology {
+        template.layout.symbology = symbology;
+    }
+    if let Some(ec_level) = ec_level {
+        template.layout.ec_level = ec_level;
+    }
+    template.validate()?;
+    Ok(template)
+}
+
+/// Labels onto PDF sheets starting `offset` slots in, or for a thermal
+/// printer.
+async fn render(
+    state: &Arc<AppState>,
+    labels: &[LabelData],
+    template: &LabelTemplate,
+    offset: usize,
+    format: Option<ThermalFormat>,
+) -> Result<(ContentType, Vec<u8>), RenderError> {
+    if offset >= template.labels_per_page() {
+        return Err(RenderError::Invalid(format!(
+            "offset must be less than the {} labels on a sheet",
+            template.labels_per_page()
+        )));
+    }
+    if labels.is_empty() {
+        return Err(RenderError::Invalid("no labels selected".into()));
+    }
+
+    let (labels, template) = (labels.to_vec(), template.clone());
+    crate::render::run(state, move |state| {
+        if let Some(format) = format {
+            let images = labels
+                .iter()
+                .map(|label| crate::util::generate_qr_label(state, label, &template))
+                .collect::<Result<Vec<_>, _>>()?;
+            return crate::thermal::encode(&images, &template, format);
+        }
+
+        let pdf = crate::util::generate_qr_pdf(state, &labels, &template, offset)?;
+        Ok((ContentType::PDF, pdf))
+    })
+    .await
+}
+
+/// Remember that labels have been printed on the template called
+/// `template_name`, with what they encoded and said.
+async fn record(
+    conn: &mut SqliteConnection,
+    state: &AppState,
+    labels: &[LabelData],
+    template_name: &str,
+    template: &LabelTemplate,
+) -> Result<(), sqlx::Error> {
+    for label in labels {
+        let payload = crate::util::qr_payload(state, label);
+        let text = label.text(&template.layout.fields).join("\n");
+        sqlx::query!(
+            "INSERT INTO label_print (kind, entity_id, payload, template, text)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (kind, entity_id) DO UPDATE SET payload = excluded.payload,
+                template = excluded.template, text = excluded.text,
+                printed_at = CURRENT_TIMESTAMP",
+            label.model_route,
+            label.id,
+            payload,
+            template_name,
+            text
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Every entity without an up to date label - never printed, or printed
+/// with a code or text that has since changed - with its label.
+async fn pending(
+    conn: &mut SqliteConnection,
+    state: &AppState,
+) -> Result<Vec<(PendingLabel, LabelData)>, sqlx::Error> {
+    let printed = sqlx::query!(
+        "SELECT kind, entity_id, payload, template, text, printed_at FROM label_print"
+    )
+    .fetch(&mut *conn)
+    .map_ok(|r| ((r.kind.clone(), r.entity_id), r))
+    .try_collect::<HashMap<_, _>>()
+    .await?;
+
+    let mut pending = Vec::new();
+    for kind in [LabelKind::Container, LabelKind::Item, LabelKind::Unit] {
+        for id in select(&mut *conn, kind, None, None).await? {
+            let label = match label_data(&mut *conn, LabelRef { kind, id }).await? {
+                Some(label) => label,
+                None => continue,
+            };
+            let (reason, printed_at) = match printed.get(&(kind.route().to_string(), id)) {
+                None => (PendingReason::Unprinted, None),
+                Some(printed) => {
+                    // compare the fields the label was printed with
+                    let template = state
+                        .templates
+                        .get(&printed.template)
+                        .or_else(|| crate::template::find(state, None));
+                    let fields = template.map_or(&[][..], |t| &t.layout.fields[..]);
+                    if printed.payload == crate::util::qr_payload(state, &label)
+                        && printed.text == label.text(fields).join("\n")
+                    {
+                        continue;
+                    }
+                    (PendingReason::Outdated, Some(printed.printed_at.clone()))
+                }
+            };
+            pending.push((
+                PendingLabel {
+                    kind,
+                    id,
+                    name: label.name.clone(),
+                    reason,
+                    printed_at,
+                },
+                label,
+            ));
+        }
+    }
+
+    Ok(pending)
+}
+
+/// Print labels for a selection of containers, items and units onto a PDF
+/// sheet, optionally starting part way down the sheet, or for a thermal
+/// printer. The labels are recorded as printed - see `list_pending`.
+#[post("/labels", data = "<request>")]
+pub async fn print(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    request: Json<PostLabels>,
+) -> Result<Result<(ContentType, Vec<u8>), RenderError>> {
+    let template = match template_for(
+        state,
+        request.template.as_deref(),
+        request.symbology,
+        request.ec_level,
+    ) {
+        Ok(template) => template,
+        Err(e) => return Ok(Err(RenderError::Invalid(e))),
+    };
+
+    let mut selected = request.ids.clone();
+    if request.subtree.is_some() || request.filter.is_some() {
+        let filter = request.filter.clone().unwrap_or_default();
+        let name = filter.name.as_deref();
+        for kind in [LabelKind::Container, LabelKind::Item, LabelKind::Unit] {
+            if !filter.kinds.is_empty() && !filter.kinds.contains(&kind) {
+                continue;
+            }
+            for id in select(&mut db, kind, request.subtree, name).await? {
+                selected.push(LabelRef { kind, id });
+            }
+        }
+    }
+
+    let mut labels = Vec::new();
+    let mut seen = HashSet::new();
+    for label in selected {
+        if !seen.insert(label) {
+            continue;
+        }
+        match label_data(&mut db, label).await? {
+            Some(data) => labels.push(data),
+            None => {
+                return Ok(Err(RenderError::Invalid(format!(
+                    "no {} with id {}",
+                    label.kind.route(),
+                    label.id
+                ))))
+            }
+        }
+    }
+
+    let printed = match render(state, &labels, &template, request.offset, request.format).await {
+        Ok(printed) => printed,
+        Err(e) => return Ok(Err(e)),
+    };
+    let template_name = request
+        .template
+        .as_deref()
+        .unwrap_or(&state.labels.default_template);
+    record(&mut db, state, &labels, template_name, &template).await?;
+    Ok(Ok(printed))
+}
+
+/// Entities that still need a label - new ones, and ones renamed, moved or
+/// otherwise changed since theirs was printed. Only labels printed through
+/// `print` or `print_pending` count.
+#[get("/labels/pending")]
+pub async fn list_pending(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+) -> Result<Json<Vec<PendingLabel>>> {
+    let pending = pending(&mut db, state).await?;
+    Ok(Json(
+        pending.into_iter().map(|(pending, _)| pending).collect(),
+    ))
+}
+
+/// Print every label `list_pending` lists, and record them as printed.
+#[post("/labels/pending?<template>&<offset>&<format>")]
+pub async fn print_pending(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    template: Option<&str>,
+    offset: Option<usize>,
+    format: Option<ThermalFormat>,
+) -> Result<Result<(ContentType, Vec<u8>), RenderError>> {
+    let template_name = template.unwrap_or(&state.labels.default_template);
+    let template = match template_for(state, Some(template_name), None, None) {
+        Ok(template) => template,
+        Err(e) => return Ok(Err(RenderError::Invalid(e))),
+    };
+
+    let labels: Vec<LabelData> = pending(&mut db, state)
+        .await?
+        .into_iter()
+        .map(|(_, label)| label)
+        .collect();
+    let printed = match render(state, &labels, &template, offset.unwrap_or(0), format).await {
+        Ok(printed) => printed,
+        Err(e) => return Ok(Err(e)),
+    };
+    record(&mut db, state, &labels, template_name, &template).await?;
+    Ok(Ok(printed))
+}
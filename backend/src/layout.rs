@@ -0,0 +1,383 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::symbol::{EcLevel, Symbology};
+use crate::text::Fonts;
+
+/// A piece of information that can be printed on a label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum LabelField {
+    Name,
+    /// Where the entity is - e.g. "1000 Washington Street / Toolchest"
+    Path,
+    Id,
+    Quantity,
+    /// What is in a container, a line per item - for manifest labels
+    Contents,
+}
+
+/// Where the text goes relative to the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum TextPosition {
+    Below,
+    Right,
+}
+
+/// How a label is shaded. The code is always printed dark on a light box so
+/// scanners can read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum LabelStyle {
+    /// Light text on a dark label
+    #[default]
+    LightOnDark,
+    /// Dark text on a light label - e.g. for thermal printers, which only
+    /// print dark
+    DarkOnLight,
+}
+
+impl LabelStyle {
+    /// The shade of the label, from 0 for black to 255 for white.
+    pub fn background(self) -> u8 {
+        match self {
+            LabelStyle::LightOnDark => 0,
+            LabelStyle::DarkOnLight => 255,
+        }
+    }
+
+    /// The shade of the text.
+    pub fn foreground(self) -> u8 {
+        255 - self.background()
+    }
+}
+
+/// What a template prints on each label, and where.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LabelLayout {
+    /// Fields in the order they are printed. Fields an entity does not have
+    /// are skipped.
+    pub fields: Vec<LabelField>,
+    /// Linear codes are always printed above the text, across the label
+    pub text_position: TextPosition,
+    pub symbology: Symbology,
+    pub ec_level: EcLevel,
+    /// How much of the label the code takes - the share of its height when
+    /// the text is below or the code is linear, or of its width when the
+    /// text is to the right. Defaults to 0.8, 0.4 for linear codes, and 0.5
+    /// beside the text.
+    pub qr_size: Option<f32>,
+    pub style: LabelStyle,
+}
+
+impl Default for LabelLayout {
+    fn default() -> Self {
+        LabelLayout {
+            fields: vec![LabelField::Name],
+            text_position: TextPosition::Below,
+            symbology: Symbology::default(),
+            ec_level: EcLevel::default(),
+            qr_size: None,
+            style: LabelStyle::default(),
+        }
+    }
+}
+
+/// Check that a `qr_size` leaves the code some room, and no more than the
+/// whole label.
+pub fn validate_qr_size(qr_size: Option<f32>) -> Result<(), String> {
+    match qr_size {
+        Some(size) if !(size > 0.0 && size <= 1.0) => Err(format!(
+            "qr_size must be more than 0 and at most 1, not {}",
+            size
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Everything a label can show about one container, item or unit.
+#[derive(Debug, Clone, Hash)]
+pub struct LabelData {
+    pub id: i64,
+    pub model_route: &'static str,
+    pub name: String,
+    pub path: Option<String>,
+    pub quantity: Option<i64>,
+    pub contents: Vec<String>,
+    /// The entity's short code, which the label's code links to
+    pub code: Option<String>,
+}
+
+impl LabelData {
+    pub fn new(id: i64, model_route: &'static str, name: String) -> Self {
+        LabelData {
+            id,
+            model_route,
+            name,
+            path: None,
+            quantity: None,
+            contents: Vec::new(),
+            code: None,
+        }
+    }
+
+    /// The lines of text printed for `fields`, in order.
+    pub fn text(&self, fields: &[LabelField]) -> Vec<String> {
+        fields.iter().flat_map(|&field| self.field(field)).collect()
+    }
+
+    /// The lines a field is printed as - none if the entity does not have it.
+    fn field(&self, field: LabelField) -> Vec<String> {
+        match field {
+            LabelField::Name => vec![self.name.clone()],
+            LabelField::Path => self.path.iter().cloned().collect(),
+            LabelField::Id => vec![format!("{} #{}", self.model_route, self.id)],
+            LabelField::Quantity => self
+                .quantity
+                .map(|q| format!("Qty {}", q))
+                .into_iter()
+                .collect(),
+            LabelField::Contents => self.contents.clone(),
+        }
+    }
+}
+
+/// One line of text, positioned by the top left corner of its line box.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLine {
+    pub text: String,
+    pub x: f32,
+    pub y: f32,
+    pub scale: f32,
+}
+
+/// A label laid out in pixels, ready to be drawn. The code is drawn on a
+/// light box at `code_x`, `code_y`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub code_x: u32,
+    pub code_y: u32,
+    pub code_width: u32,
+    pub code_height: u32,
+    pub lines: Vec<TextLine>,
+}
+
+/// Smallest text we will shrink to before giving up and truncating, in pixels.
+const MIN_SCALE: f32 = 10.0;
+/// Other fields are printed smaller than the name.
+const SECONDARY_SCALE: f32 = 0.7;
+/// Blank space around the text, as a fraction of the text height.
+const PADDING: f32 = 0.1;
+
+pub fn text_width(fonts: &Fonts, scale: f32, text: &str) -> f32 {
+    fonts.width(text, scale)
+}
+
+/// Greedily wrap text into lines no wider than `width`. Words that are too
+/// wide on their own are broken between characters.
+fn wrap(fonts: &Fonts, scale: f32, text: &str, width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{} {}", line, word)
+        };
+        if text_width(fonts, scale, &candidate) <= width {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for c in word.chars() {
+            line.push(c);
+            if text_width(fonts, scale, &line) > width && line.chars().count() > 1 {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, c.to_string()));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Shorten text with an ellipsis until it fits in `width`.
+fn truncate(fonts: &Fonts, scale: f32, text: &str, width: f32) -> String {
+    let mut text = text.to_string();
+    while text_width(fonts, scale, &format!("{}…", text)) > width && !text.is_empty() {
+        text.pop();
+    }
+    format!("{}…", text.trim_end())
+}
+
+/// Wrap the fields into a box, shrinking the text until it fits. If it still
+/// does not fit at the smallest size, lines that do not fit are dropped and
+/// the last one that does is truncated.
+fn fit_text(fonts: &Fonts, fields: &[(String, f32)], width: f32, height: f32) -> Vec<(String, f32)> {
+    let line_height = |weight: f32, scale: f32| weight * scale * (1.0 + PADDING);
+    let weights: f32 = fields.iter().map(|(_, weight)| weight).sum();
+    let mut scale = height / (weights * (1.0 + PADDING));
+
+    loop {
+        let lines: Vec<(String, f32)> = fields
+            .iter()
+            .flat_map(|(text, weight)| {
+                wrap(fonts, weight * scale, text, width)
+                    .into_iter()
+                    .map(move |line| (line, *weight))
+            })
+            .collect();
+        let total: f32 = lines.iter().map(|(_, w)| line_height(*w, scale)).sum();
+        if total <= height || scale <= MIN_SCALE {
+            let mut used = 0.0;
+            let mut fitted: Vec<(String, f32)> = Vec::new();
+            for (line, weight) in lines {
+                used += line_height(weight, scale);
+                if used > height {
+                    // out of room - mark the last line that fits as cut short
+                    if let Some((last, last_scale)) = fitted.pop() {
+                        fitted.push((truncate(fonts, last_scale, &last, width), last_scale));
+                    }
+                    break;
+                }
+                fitted.push((line, weight * scale));
+            }
+            return fitted;
+        }
+        scale = (scale * 0.9).max(MIN_SCALE);
+    }
+}
+
+/// Lay out a `width` by `height` pixel label - a code and as many of the
+/// layout's fields as the entity has, shrunk and wrapped to fit.
+pub fn layout(
+    fonts: &Fonts,
+    label: &LabelData,
+    label_layout: &LabelLayout,
+    width: u32,
+    height: u32,
+) -> Layout {
+    let fields: Vec<(String, f32)> = label_layout
+        .fields
+        .iter()
+        .flat_map(|&field| {
+            let weight = if field == LabelField::Name {
+                1.0
+            } else {
+                SECONDARY_SCALE
+            };
+            label
+                .field(field)
+                .into_iter()
+                .map(move |text| (text, weight))
+        })
+        .collect();
+
+    let linear = label_layout.symbology.is_linear();
+    // `length` scaled by `qr_size`, or by `numerator / denominator` if unset
+    let share = |length: u32, (numerator, denominator): (u32, u32)| match label_layout.qr_size {
+        Some(size) => (length as f32 * size).round() as u32,
+        None => length * numerator / denominator,
+    };
+    if fields.is_empty() {
+        let (code_width, code_height) = if linear {
+            (width, height)
+        } else {
+            (width.min(height), width.min(height))
+        };
+        return Layout {
+            width,
+            height,
+            code_x: (width - code_width) / 2,
+            code_y: (height - code_height) / 2,
+            code_width,
+            code_height,
+            lines: Vec::new(),
+        };
+    }
+
+    let (code_x, code_y, code_width, code_height, box_x, box_y, box_width, box_height) =
+        match label_layout.text_position {
+            _ if linear => {
+                let code_height = share(height, (2, 5));
+                let box_y = code_height as f32;
+                (
+                    0,
+                    0,
+                    width,
+                    code_height,
+                    0.0,
+                    box_y,
+                    width as f32,
+                    height as f32 - box_y,
+                )
+            }
+            TextPosition::Below => {
+                let qr_size = width.min(share(height, (4, 5)));
+                let box_y = qr_size as f32;
+                (
+                    (width - qr_size) / 2,
+                    0,
+                    qr_size,
+                    qr_size,
+                    0.0,
+                    box_y,
+                    width as f32,
+                    height as f32 - box_y,
+                )
+            }
+            TextPosition::Right => {
+                let qr_size = height.min(share(width, (1, 2)));
+                let box_x = qr_size as f32;
+                (
+                    0,
+                    (height - qr_size) / 2,
+                    qr_size,
+                    qr_size,
+                    box_x,
+                    0.0,
+                    width as f32 - box_x,
+                    height as f32,
+                )
+            }
+        };
+
+    let padding = box_height.min(box_width) * PADDING / 2.0;
+    let fitted = fit_text(
+        fonts,
+        &fields,
+        box_width - 2.0 * padding,
+        box_height - 2.0 * padding,
+    );
+
+    // center the block of text vertically in its box
+    let block_height: f32 = fitted.iter().map(|(_, s)| s * (1.0 + PADDING)).sum();
+    let mut y = box_y + (box_height - block_height) / 2.0;
+    let mut lines = Vec::new();
+    for (text, scale) in fitted {
+        lines.push(TextLine {
+            text,
+            x: box_x + padding,
+            y: y + scale * PADDING / 2.0,
+            scale,
+        });
+        y += scale * (1.0 + PADDING);
+    }
+
+    Layout {
+        width,
+        height,
+        code_x,
+        code_y,
+        code_width,
+        code_height,
+        lines,
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use rocket::response::Debug;
+use rocket::State;
+use rocket_db_pools::{sqlx, Connection};
+
+use crate::container::Container;
+use crate::item::Item;
+use crate::unit::Unit;
+use crate::AppState;
+use crate::Db;
+
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+/// Whatever a scanned code turned out to refer to.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "snake_case")]
+pub enum Lookup {
+    Container(Container),
+    Item(Item),
+    Unit(Unit),
+}
+
+/// Split one of our own QR payloads from before short codes (see
+/// `util::qr_payload`) into its model route and id. Payloads printed under a
+/// different `root_url` are still accepted, so old labels keep working after
+/// a domain move.
+pub fn parse_payload<'a>(state: &AppState, code: &'a str) -> Option<(&'a str, i64)> {
+    let code = code.trim();
+    let path = match code.strip_prefix(state.root_url.as_str()) {
+        Some(path) => path,
+        None => {
+            let (_, rest) = code.split_once("://")?;
+            &rest[rest.find('/')?..]
+        }
+    };
+    let mut segments = path.trim_matches('/').rsplit('/');
+    let id = segments.next()?.parse().ok()?;
+    let model = segments.next()?;
+    Some((model, id))
+}
+
+/// Resolve a scanned code - a manufacturer barcode registered to an item, a
+/// unit's serial number, one of our short codes, or one of our own QR
+/// payloads - to the entity it refers to.
+pub async fn resolve(
+    db: &mut Connection<Db>,
+    state: &AppState,
+    code: &str,
+) -> Result<Option<Lookup>, sqlx::Error> {
+    if let Some(item_id) = crate::barcode::find_item(db, code).await? {
+        return Ok(crate::item::fetch(db, item_id).await.map(Lookup::Item));
+    }
+
+    if let Some(unit_id) = crate::unit::find_by_serial(db, code.trim()).await {
+        return Ok(crate::unit::fetch(db, unit_id).await.map(Lookup::Unit));
+    }
+
+    let (model, id) = match crate::short_code::find(db, code).await {
+        Some(entity) => entity,
+        None => match parse_payload(state, code) {
+            Some((model, id)) => (model.to_string(), id),
+            None => return Ok(None),
+        },
+    };
+    Ok(match model.as_str() {
+        "container" => crate::container::fetch(db, id).await.map(Lookup::Container),
+        "item" => crate::item::fetch(db, id).await.map(Lookup::Item),
+        "unit" => crate::unit::fetch(db, id).await.map(Lookup::Unit),
+        _ => None,
+    })
+}
+
+/// Look up one scanned code - see `resolve`.
+#[get("/lookup?<code>")]
+pub async fn lookup(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    code: &str,
+) -> Result<Option<Json<Lookup>>, Debug<sqlx::Error>> {
+    Ok(resolve(&mut db, state, code).await?.map(Json))
+}
@@ -10,12 +10,38 @@ use rocket::{Build, Rocket};
 
 use genpdf::Document;
 
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use template::LabelTemplate;
+
+mod attachment;
+mod barcode;
+mod blob;
+mod bom;
+mod config;
 mod container;
 mod item;
 mod item_location;
+mod kit;
+mod labels;
+mod layout;
+mod lookup;
+mod manifest;
+mod page;
+mod photo;
+mod render;
+mod scan;
+mod short_code;
+mod similar;
+mod symbol;
+mod template;
+mod text;
+mod thermal;
+mod unit;
+mod upload;
 mod util;
-
-const QR_CODE_DIMENSION: usize = 300;
+mod vector;
 
 #[cfg(test)]
 mod tests;
@@ -31,7 +57,16 @@ pub struct Db(sqlx::SqlitePool);
 pub struct Db(sqlx::SqlitePool);
 
 pub struct AppState {
+    /// Where label codes link to, without a trailing slash
     pub root_url: String,
+    /// Label sheet templates by name - the builtin ones plus any from config
+    pub templates: HashMap<String, LabelTemplate>,
+    pub labels: config::LabelConfig,
+    /// The fonts labels are printed in
+    pub fonts: text::Fonts,
+    pub renderer: render::Renderer,
+    /// Where photos and attachments are kept
+    pub blobs: blob::BlobStore,
 }
 
 #[get("/")]
@@ -41,15 +76,13 @@ fn index() -> &'static str {
 
 #[launch]
 fn rocket() -> _ {
-    let state = AppState {
-        root_url: String::from("http://foobar.com"), // have to set this in prod
-    };
-
     rocket::build()
         .attach(Db::init())
         .attach(AdHoc::try_on_ignite("SQLx Migrations", run_migrations))
-        .manage(state)
-        .mount("/", routes![index])
+        .attach(AdHoc::try_on_ignite("Short Codes", assign_short_codes))
+        .attach(AdHoc::try_on_ignite("App State", init_state))
+        .attach(AdHoc::try_on_ignite("Blob Store", move_blobs))
+        .mount("/", routes![index, config::read])
         .mount(
             "/",
             routes![
@@ -57,6 +90,8 @@ fn rocket() -> _ {
                 container::read,
                 container::delete,
                 container::read_qr,
+                container::read_svg,
+                container::read_thermal,
                 container::list_qr,
                 container::list,
                 container::full_update
@@ -69,19 +104,92 @@ fn rocket() -> _ {
                 item::read,
                 item::delete,
                 item::read_qr,
+                item::read_svg,
+                item::read_thermal,
                 item::list_qr,
                 item::list,
                 item::full_update
             ],
         )
+        .mount(
+            "/",
+            routes![
+                photo::create,
+                photo::list,
+                photo::read,
+                photo::update,
+                photo::delete,
+                photo::upload_primary,
+                photo::read_primary,
+                photo::delete_primary
+            ],
+        )
         .mount(
             "/",
             routes![
                 item_location::create,
                 item_location::read,
-                item_location::delete
+                item_location::delete,
+                item_location::list_expiring,
+                item_location::consume,
+                item_location::adjust
+            ],
+        )
+        .mount(
+            "/",
+            routes![
+                unit::create,
+                unit::read,
+                unit::delete,
+                unit::read_qr,
+                unit::read_svg,
+                unit::read_thermal,
+                unit::list_qr,
+                unit::list,
+                unit::full_update
             ],
         )
+        .mount(
+            "/",
+            routes![barcode::create, barcode::list, barcode::delete],
+        )
+        .mount(
+            "/",
+            routes![
+                lookup::lookup,
+                scan::scan,
+                short_code::redirect,
+                similar::search
+            ],
+        )
+        .mount(
+            "/",
+            routes![
+                kit::create,
+                kit::list,
+                kit::delete,
+                kit::read_availability,
+                kit::build
+            ],
+        )
+        .mount("/", routes![bom::read_kicad, bom::pick_kicad])
+        .mount("/", routes![page::container, page::item, page::unit])
+        .mount("/", routes![template::list])
+        .mount(
+            "/",
+            routes![labels::print, labels::list_pending, labels::print_pending],
+        )
+        .mount("/", routes![manifest::print, manifest::list_stale])
+        .mount(
+            "/",
+            routes![
+                attachment::create,
+                attachment::list,
+                attachment::read,
+                attachment::delete
+            ],
+        )
+        .mount("/", routes![blob::gc])
 }
 
 async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
@@ -96,3 +204,120 @@ async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
         None => Err(rocket),
     }
 }
+
+/// Give entities created before short codes were their codes, so label
+/// routes only ever read them.
+async fn assign_short_codes(rocket: Rocket<Build>) -> fairing::Result {
+    let db = match Db::fetch(&rocket) {
+        Some(db) => db,
+        None => return Err(rocket),
+    };
+    let assigned = match db.acquire().await {
+        Ok(mut conn) => short_code::assign_missing(&mut conn).await,
+        Err(e) => Err(e),
+    };
+    match assigned {
+        Ok(0) => Ok(rocket),
+        Ok(assigned) => {
+            info!("Gave {} entities short codes", assigned);
+            Ok(rocket)
+        }
+        Err(e) => {
+            error!("Failed to assign short codes: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+async fn init_state(rocket: Rocket<Build>) -> fairing::Result {
+    let mut config = match rocket.figment().extract::<config::Config>() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to read config: {}", e);
+            return Err(rocket);
+        }
+    };
+    if let Err(e) = config.validate() {
+        error!("Invalid config: {}", e);
+        return Err(rocket);
+    }
+    let fonts = match config.labels.load_fonts() {
+        Ok(fonts) => fonts,
+        Err(e) => {
+            error!("Failed to load label fonts: {}", e);
+            return Err(rocket);
+        }
+    };
+
+    let mut templates = template::builtin();
+    match rocket
+        .figment()
+        .extract_inner::<HashMap<String, LabelTemplate>>("label_templates")
+    {
+        Ok(configured) => templates.extend(configured),
+        Err(e) if e.missing() => {}
+        Err(e) => {
+            error!("Failed to read label templates: {}", e);
+            return Err(rocket);
+        }
+    }
+    for (name, template) in templates.iter_mut() {
+        if template.layout.qr_size.is_none() {
+            template.layout.qr_size = config.labels.qr_size;
+        }
+        if let Err(e) = template.validate() {
+            error!("Invalid label template {}: {}", name, e);
+            return Err(rocket);
+        }
+    }
+
+    if !templates.contains_key(&config.labels.default_template) {
+        error!(
+            "Default label template {} does not exist",
+            config.labels.default_template
+        );
+        return Err(rocket);
+    }
+
+    let blobs = match blob::BlobStore::open(&config.blobs) {
+        Ok(blobs) => blobs,
+        Err(e) => {
+            error!("Failed to open blob store: {}", e);
+            return Err(rocket);
+        }
+    };
+
+    let state = AppState {
+        root_url: config.root_url,
+        templates,
+        renderer: render::Renderer::new(&config.labels),
+        labels: config.labels,
+        fonts,
+        blobs,
+    };
+
+    Ok(rocket.manage(Arc::new(state)))
+}
+
+/// Move photos stored in the database before the blob store into it.
+async fn move_blobs(rocket: Rocket<Build>) -> fairing::Result {
+    let (db, state) = match (Db::fetch(&rocket), rocket.state::<Arc<AppState>>()) {
+        (Some(db), Some(state)) => (db, state),
+        _ => return Err(rocket),
+    };
+    let moved = match db.acquire().await {
+        Ok(mut conn) => blob::move_inline(&mut conn, &state.blobs).await,
+        Err(e) => Err(e.into()),
+    };
+    match moved {
+        Ok(0) => Ok(rocket),
+        Ok(moved) => {
+            info!("Moved {} photos into the blob store", moved);
+            Ok(rocket)
+        }
+        Err(e) => {
+            error!("Failed to move photos into the blob store: {}", e);
+            Err(rocket)
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::rocket::futures::TryStreamExt;
+use rocket::http::ContentType;
+use rocket::State;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::layout::LabelField;
+use crate::render::RenderError;
+use crate::AppState;
+use crate::Db;
+
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// The template manifests are printed on unless another is asked for.
+pub const TEMPLATE: &str = "avery-l7165";
+
+/// A manifest label that no longer lists what is in its container.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StaleManifest {
+    pub container_id: i64,
+    pub name: String,
+    pub printed_at: String,
+    /// What the label lists
+    pub printed: Vec<String>,
+    /// What is in the container now
+    pub current: Vec<String>,
+}
+
+/// What is in a container, a line per item with how many there are - e.g.
+/// "5 × Resistor". Items that have run out are left off.
+pub async fn contents(conn: &mut SqliteConnection, id: i64) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!(
+        r#"SELECT item.name, SUM(quantity) AS "quantity: i64"
+        FROM item_location JOIN item ON item.id = item_location.item_id
+        WHERE container_id = ? AND (quantity IS NULL OR quantity > 0)
+        GROUP BY item.id ORDER BY item.name, item.id"#,
+        id
+    )
+    .fetch(conn)
+    .map_ok(|r| match r.quantity {
+        Some(quantity) => format!("{} × {}", quantity, r.name),
+        None => r.name,
+    })
+    .try_collect()
+    .await
+}
+
+/// Print a manifest label for a sealed container - its code, and a list of
+/// what is in it - and remember what it listed, so it shows up in
+/// `list_stale` once that changes. Any template can be used; the contents
+/// are added to its fields if it does not have them.
+#[post("/container/manifest/<id>?<template>")]
+pub async fn print(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    template: Option<&str>,
+) -> Result<Option<Result<(ContentType, Vec<u8>), RenderError>>> {
+    let mut template = match state.templates.get(template.unwrap_or(TEMPLATE)) {
+        Some(template) => template.clone(),
+        None => return Ok(None),
+    };
+    if !template.layout.fields.contains(&LabelField::Contents) {
+        template.layout.fields.push(LabelField::Contents);
+    }
+
+    let mut label = match crate::container::label_data(&mut db, id).await {
+        Ok(label) => label,
+        Err(sqlx::Error::RowNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    label.contents = contents(&mut db, id).await?;
+    let labels = vec![label.clone()];
+    let pdf = crate::render::run(state, move |state| {
+        crate::util::generate_qr_pdf(state, &labels, &template, 0)
+    })
+    .await;
+    let pdf = match pdf {
+        Ok(pdf) => pdf,
+        Err(e) => return Ok(Some(Err(e))),
+    };
+
+    let printed = rocket::serde::json::to_string(&label.contents).unwrap();
+    sqlx::query!(
+        "INSERT INTO manifest (container_id, contents) VALUES (?, ?)
+        ON CONFLICT (container_id) DO UPDATE
+        SET contents = excluded.contents, printed_at = CURRENT_TIMESTAMP",
+        id,
+        printed
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(Some(Ok((ContentType::PDF, pdf))))
+}
+
+/// Containers whose manifest label lists something other than what is in
+/// them now, and should be reprinted.
+#[get("/container/manifest/stale")]
+pub async fn list_stale(mut db: Connection<Db>) -> Result<Json<Vec<StaleManifest>>> {
+    let manifests = sqlx::query!(
+        "SELECT manifest.container_id, container.name, manifest.contents, manifest.printed_at
+        FROM manifest JOIN container ON container.id = manifest.container_id
+        ORDER BY manifest.container_id"
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let mut stale = Vec::new();
+    for manifest in manifests {
+        let current = contents(&mut db, manifest.container_id).await?;
+        let printed: Vec<String> =
+            rocket::serde::json::from_str(&manifest.contents).unwrap_or_default();
+        if printed != current {
+            stale.push(StaleManifest {
+                container_id: manifest.container_id,
+                name: manifest.name,
+                printed_at: manifest.printed_at,
+                printed,
+                current,
+            });
+        }
+    }
+
+    Ok(Json(stale))
+}
@@ -0,0 +1,414 @@
+use std::fmt::Write;
+
+use crate::rocket::futures::TryStreamExt;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::RawHtml;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::photo::PhotoKind;
+use crate::Db;
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// A request from a browser - e.g. a phone that has just scanned a label.
+/// Requests that would rather have JSON, or take anything, are forwarded to
+/// the JSON routes.
+pub struct Browser;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Browser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        match request.accept() {
+            Some(accept) if accept.preferred().media_type().is_html() => Outcome::Success(Browser),
+            _ => Outcome::Forward(()),
+        }
+    }
+}
+
+/// Escape text for use in HTML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const STYLE: &str = "\
+body{font-family:system-ui,sans-serif;line-height:1.4;max-width:40em;margin:0 auto;padding:1em}\
+nav{color:#666;font-size:.9em}nav a{color:inherit}\
+h1{margin:.3em 0 0}.code{font-family:monospace;color:#666}\
+img{max-width:100%;border-radius:.5em}\
+dt{color:#666;font-size:.9em}dd{margin:0 0 .5em}\
+ul{list-style:none;padding:0}\
+li{display:flex;align-items:center;gap:.5em;min-height:2.5em;border-bottom:1px solid #ddd}\
+li>:first-child{flex:1}\
+form{display:flex;align-items:center;gap:.5em;margin:0}\
+button{font-size:1.3em;width:2em;height:2em;border:1px solid #999;border-radius:50%;background:#f4f4f4}";
+
+/// A complete page, sized for phones.
+fn page(title: &str, body: &str) -> RawHtml<String> {
+    RawHtml(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{}</title>
+<style>{}</style>
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape(title),
+        STYLE,
+        body
+    ))
+}
+
+/// Links to a container and each container it is inside, outermost first.
+async fn breadcrumb(conn: &mut SqliteConnection, container_id: i64) -> Result<String, sqlx::Error> {
+    let links: Vec<String> = crate::container::ancestors(conn, container_id)
+        .await?
+        .iter()
+        .map(|(id, name)| format!(r#"<a href="/container/{}">{}</a>"#, id, escape(name)))
+        .collect();
+    Ok(links.join(" / "))
+}
+
+/// The photo to show on an entity's page - its primary photo, or the next
+/// one if that is not in a format browsers show.
+async fn photo(
+    conn: &mut SqliteConnection,
+    kind: PhotoKind,
+    entity_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    let kind = kind.as_str();
+    let photo = sqlx::query!(
+        r#"SELECT id AS "id!" FROM photo
+        WHERE kind = ? AND entity_id = ? AND content_type LIKE 'image/%'
+        ORDER BY is_primary DESC, id LIMIT 1"#,
+        kind,
+        entity_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(photo.map(|photo| photo.id))
+}
+
+/// The heading every page starts with - the name and short code, then the
+/// primary photo and note if there are any. The photo is shown as a
+/// thumbnail linking to the full image.
+fn header(
+    html: &mut String,
+    name: &str,
+    code: Option<&str>,
+    photo: Option<i64>,
+    note: Option<&str>,
+) {
+    write!(html, "<h1>{}</h1>", escape(name)).unwrap();
+    if let Some(code) = code {
+        write!(html, r#"<div class="code">{}</div>"#, escape(code)).unwrap();
+    }
+    if let Some(photo) = photo {
+        write!(
+            html,
+            r#"<p><a href="/photo/{0}"><img src="/photo/{0}?size=medium" alt=""></a></p>"#,
+            photo
+        )
+        .unwrap();
+    }
+    if let Some(note) = note {
+        write!(html, "<p>{}</p>", escape(note)).unwrap();
+    }
+}
+
+/// A quantity between buttons to take one out and put one back, which
+/// return to the page at `back`.
+fn quantity_buttons(item_location_id: i64, quantity: Option<i64>, back: &str) -> String {
+    format!(
+        r#"<form method="post" action="/itemloc/{}/adjust"><input type="hidden" name="back" value="{}"><button name="delta" value="-1" aria-label="Take one out">&minus;</button><span>{}</span><button name="delta" value="1" aria-label="Put one back">+</button></form>"#,
+        item_location_id,
+        escape(back),
+        quantity.map_or("?".to_string(), |q| q.to_string())
+    )
+}
+
+/// How a lot is told apart from other stock of the same item.
+fn lot(lot: Option<&str>, expires_on: Option<&str>) -> String {
+    match (lot, expires_on) {
+        (Some(lot), Some(expires_on)) => format!(" (lot {}, expires {})", lot, expires_on),
+        (Some(lot), None) => format!(" (lot {})", lot),
+        (None, Some(expires_on)) => format!(" (expires {})", expires_on),
+        (None, None) => String::new(),
+    }
+}
+
+/// A container's page - where it is, and what is in it. Ranked ahead of
+/// `container::read`, which API clients get.
+#[get("/container/<id>", rank = 1)]
+pub async fn container(
+    _browser: Browser,
+    mut db: Connection<Db>,
+    id: i64,
+) -> Result<Option<RawHtml<String>>> {
+    let container = match sqlx::query!(
+        "SELECT parent_container_id, name, note FROM container WHERE id = ?",
+        id
+    )
+    .fetch_optional(&mut *db)
+    .await?
+    {
+        Some(container) => container,
+        None => return Ok(None),
+    };
+
+    let mut html = String::new();
+    if let Some(parent_id) = container.parent_container_id {
+        write!(html, "<nav>{}</nav>", breadcrumb(&mut db, parent_id).await?).unwrap();
+    }
+    let code = crate::short_code::get(&mut db, "container", id).await?;
+    let photo = photo(&mut db, PhotoKind::Container, id).await?;
+    header(
+        &mut html,
+        &container.name,
+        code.as_deref(),
+        photo,
+        container.note.as_deref(),
+    );
+
+    let children = sqlx::query!(
+        r#"SELECT id AS "id!", name FROM container WHERE parent_container_id = ? ORDER BY name"#,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+    let locations = sqlx::query!(
+        r#"SELECT item_location.id AS "id!", item_id, item.name, quantity, lot, expires_on
+        FROM item_location JOIN item ON item.id = item_location.item_id
+        WHERE container_id = ? ORDER BY item.name, item_location.id"#,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+    let units = sqlx::query!(
+        r#"SELECT unit.id AS "id!", serial_number, item.name
+        FROM unit JOIN item ON item.id = unit.item_id
+        WHERE container_id = ? ORDER BY item.name, serial_number"#,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let back = format!("/container/{}", id);
+    html.push_str("<h2>Contents</h2><ul>");
+    for child in &children {
+        write!(
+            html,
+            r#"<li><a href="/container/{}">{}</a></li>"#,
+            child.id,
+            escape(&child.name)
+        )
+        .unwrap();
+    }
+    for location in &locations {
+        write!(
+            html,
+            r#"<li><a href="/item/{}">{}{}</a>{}</li>"#,
+            location.item_id,
+            escape(&location.name),
+            escape(&lot(
+                location.lot.as_deref(),
+                location.expires_on.as_deref()
+            )),
+            quantity_buttons(location.id, location.quantity, &back)
+        )
+        .unwrap();
+    }
+    for unit in &units {
+        write!(
+            html,
+            r#"<li><a href="/unit/{}">{} S/N {}</a></li>"#,
+            unit.id,
+            escape(&unit.name),
+            escape(&unit.serial_number)
+        )
+        .unwrap();
+    }
+    if children.is_empty() && locations.is_empty() && units.is_empty() {
+        html.push_str("<li>Empty</li>");
+    }
+    html.push_str("</ul>");
+
+    Ok(Some(page(&container.name, &html)))
+}
+
+/// An item's page - what it is, and everywhere it is kept. Ranked ahead of
+/// `item::read`, which API clients get.
+#[get("/item/<id>", rank = 1)]
+pub async fn item(
+    _browser: Browser,
+    mut db: Connection<Db>,
+    id: i64,
+) -> Result<Option<RawHtml<String>>> {
+    let item = match sqlx::query!(
+        "SELECT name, note, mpn, value, footprint FROM item WHERE id = ?",
+        id
+    )
+    .fetch_optional(&mut *db)
+    .await?
+    {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+
+    let mut html = String::new();
+    let code = crate::short_code::get(&mut db, "item", id).await?;
+    let photo = photo(&mut db, PhotoKind::Item, id).await?;
+    header(
+        &mut html,
+        &item.name,
+        code.as_deref(),
+        photo,
+        item.note.as_deref(),
+    );
+
+    let stock = crate::item_location::stock(&mut db, id).await?;
+    write!(html, "<dl><dt>In stock</dt><dd>{}</dd>", stock).unwrap();
+    for (term, value) in [
+        ("Part number", &item.mpn),
+        ("Value", &item.value),
+        ("Footprint", &item.footprint),
+    ] {
+        if let Some(value) = value {
+            write!(html, "<dt>{}</dt><dd>{}</dd>", term, escape(value)).unwrap();
+        }
+    }
+    html.push_str("</dl>");
+
+    let locations = sqlx::query!(
+        r#"SELECT id AS "id!", container_id, quantity, lot, expires_on FROM item_location
+        WHERE item_id = ? ORDER BY id"#,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+    if !locations.is_empty() {
+        let back = format!("/item/{}", id);
+        html.push_str("<h2>Locations</h2><ul>");
+        for location in &locations {
+            write!(
+                html,
+                "<li><span>{}{}</span>{}</li>",
+                breadcrumb(&mut db, location.container_id).await?,
+                escape(&lot(
+                    location.lot.as_deref(),
+                    location.expires_on.as_deref()
+                )),
+                quantity_buttons(location.id, location.quantity, &back)
+            )
+            .unwrap();
+        }
+        html.push_str("</ul>");
+    }
+
+    let units = sqlx::query!(
+        r#"SELECT id AS "id!", serial_number, container_id FROM unit
+        WHERE item_id = ? ORDER BY serial_number"#,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+    if !units.is_empty() {
+        html.push_str("<h2>Units</h2><ul>");
+        for unit in &units {
+            write!(
+                html,
+                r#"<li><a href="/unit/{}">S/N {}</a><span>{}</span></li>"#,
+                unit.id,
+                escape(&unit.serial_number),
+                breadcrumb(&mut db, unit.container_id).await?
+            )
+            .unwrap();
+        }
+        html.push_str("</ul>");
+    }
+
+    Ok(Some(page(&item.name, &html)))
+}
+
+/// A unit's page - which item it is and where it is. Ranked ahead of
+/// `unit::read`, which API clients get.
+#[get("/unit/<id>", rank = 1)]
+pub async fn unit(
+    _browser: Browser,
+    mut db: Connection<Db>,
+    id: i64,
+) -> Result<Option<RawHtml<String>>> {
+    let unit = match sqlx::query!(
+        "SELECT unit.item_id, unit.container_id, unit.serial_number, unit.purchase_date,
+            unit.condition, unit.note, item.name
+        FROM unit JOIN item ON item.id = unit.item_id WHERE unit.id = ?",
+        id
+    )
+    .fetch_optional(&mut *db)
+    .await?
+    {
+        Some(unit) => unit,
+        None => return Ok(None),
+    };
+
+    let name = format!("{} S/N {}", unit.name, unit.serial_number);
+    let mut html = String::new();
+    write!(
+        html,
+        "<nav>{}</nav>",
+        breadcrumb(&mut db, unit.container_id).await?
+    )
+    .unwrap();
+    let code = crate::short_code::get(&mut db, "unit", id).await?;
+    let photo = photo(&mut db, PhotoKind::Item, unit.item_id).await?;
+    header(
+        &mut html,
+        &name,
+        code.as_deref(),
+        photo,
+        unit.note.as_deref(),
+    );
+
+    write!(
+        html,
+        r#"<dl><dt>Item</dt><dd><a href="/item/{}">{}</a></dd>"#,
+        unit.item_id,
+        escape(&unit.name)
+    )
+    .unwrap();
+    for (term, value) in [
+        ("Condition", &unit.condition),
+        ("Purchased", &unit.purchase_date),
+    ] {
+        if let Some(value) = value {
+            write!(html, "<dt>{}</dt><dd>{}</dd>", term, escape(value)).unwrap();
+        }
+    }
+    html.push_str("</dl>");
+
+    Ok(Some(page(&name, &html)))
+}
@@ -0,0 +1,665 @@
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::rocket::futures::TryStreamExt;
+use rocket::data::ToByteUnit;
+use rocket::form::{self, DataField, Form, FromFormField};
+use rocket::http::{ContentType, Header};
+use rocket::request::FromParam;
+use rocket::response::status::Created;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::sqlx::{self, Acquire, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use printpdf::image_crate::imageops::FilterType;
+use printpdf::image_crate::{self, DynamicImage, ImageFormat, ImageOutputFormat};
+
+use crate::blob::{BlobStore, StoreError};
+use crate::upload::UploadError;
+use crate::{AppState, Db};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// A photo uploaded as a file in a multipart form.
+#[derive(Debug, Clone)]
+pub struct Photo {
+    pub data: Vec<u8>,
+    /// Worked out from the image itself rather than trusted from the upload
+    pub content_type: ContentType,
+}
+
+/// The form photos are uploaded with - the image in a `photo` field, and
+/// optionally a caption and whether it becomes the primary photo.
+#[derive(Debug, FromForm)]
+pub struct PhotoUpload {
+    pub photo: Photo,
+    pub caption: Option<String>,
+    pub primary: bool,
+}
+
+/// The kinds of thing that have photos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum PhotoKind {
+    Container,
+    Item,
+    ItemLocation,
+}
+
+impl PhotoKind {
+    /// How the kind is stored in the `photo` table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PhotoKind::Container => "container",
+            PhotoKind::Item => "item",
+            PhotoKind::ItemLocation => "item_location",
+        }
+    }
+
+    /// Whether the entity photos would be added to exists.
+    async fn exists(&self, conn: &mut SqliteConnection, id: i64) -> Result<bool, sqlx::Error> {
+        let found = match self {
+            PhotoKind::Container => sqlx::query!("SELECT id FROM container WHERE id = ?", id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .is_some(),
+            PhotoKind::Item => sqlx::query!("SELECT id FROM item WHERE id = ?", id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .is_some(),
+            PhotoKind::ItemLocation => {
+                sqlx::query!("SELECT id FROM item_location WHERE id = ?", id)
+                    .fetch_optional(&mut *conn)
+                    .await?
+                    .is_some()
+            }
+        };
+        Ok(found)
+    }
+}
+
+/// The kind as the first segment of its entities' routes.
+impl<'a> FromParam<'a> for PhotoKind {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        match param {
+            "container" => Ok(PhotoKind::Container),
+            "item" => Ok(PhotoKind::Item),
+            "itemloc" => Ok(PhotoKind::ItemLocation),
+            _ => Err(param),
+        }
+    }
+}
+
+/// The sizes thumbnails are made in, for list views and pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 3] = [
+        ThumbnailSize::Small,
+        ThumbnailSize::Medium,
+        ThumbnailSize::Large,
+    ];
+
+    /// The longest side of the thumbnail.
+    pub fn pixels(self) -> u32 {
+        match self {
+            ThumbnailSize::Small => 160,
+            ThumbnailSize::Medium => 480,
+            ThumbnailSize::Large => 1024,
+        }
+    }
+}
+
+/// A photo without its image - see `read` for that.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PhotoInfo {
+    pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    pub primary: bool,
+    pub content_type: String,
+    /// Upright, after any EXIF orientation. Unknown for photos that were
+    /// stored before galleries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<i64>,
+    pub created_at: String,
+}
+
+/// What can be changed about a photo once it is uploaded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PutPhoto {
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+/// An image, or a thumbnail of one, as it is served.
+#[derive(Responder)]
+pub struct PhotoData {
+    body: (ContentType, Vec<u8>),
+    cache_control: Header<'static>,
+}
+
+/// A photo ready to be stored - the original as uploaded, and what is
+/// worked out from it.
+pub struct Prepared {
+    data: Vec<u8>,
+    content_type: String,
+    dimensions: Option<(u32, u32)>,
+    thumbnails: Vec<(u32, Vec<u8>)>,
+    dhash: Option<u64>,
+}
+
+impl Prepared {
+    /// Decode an uploaded photo to find its size and perceptual hash, and
+    /// make its thumbnails. Slow - run it on the blocking pool.
+    pub fn new(photo: Photo) -> Result<Self, String> {
+        let image = upright(&photo.data)?;
+        let thumbnails = ThumbnailSize::ALL
+            .iter()
+            .map(|size| Ok((size.pixels(), thumbnail(&image, size.pixels())?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Prepared {
+            content_type: photo.content_type.to_string(),
+            dimensions: Some((image.width(), image.height())),
+            data: photo.data,
+            thumbnails,
+            dhash: Some(dhash(&image)),
+        })
+    }
+
+    /// Raw bytes stored as they are, with thumbnails and the perceptual hash
+    /// made when first needed - for photos given as JSON, which may not be
+    /// images at all.
+    pub fn raw(data: Vec<u8>) -> Self {
+        Prepared {
+            content_type: content_type(&data)
+                .unwrap_or(ContentType::Binary)
+                .to_string(),
+            data,
+            dimensions: None,
+            thumbnails: Vec::new(),
+            dhash: None,
+        }
+    }
+}
+
+/// The type of an image in a format browsers show, from its first bytes.
+pub fn content_type(data: &[u8]) -> Option<ContentType> {
+    match image_crate::guess_format(data).ok()? {
+        ImageFormat::Png => Some(ContentType::PNG),
+        ImageFormat::Jpeg => Some(ContentType::JPEG),
+        ImageFormat::Gif => Some(ContentType::GIF),
+        ImageFormat::WebP => Some(ContentType::WEBP),
+        _ => None,
+    }
+}
+
+/// How the camera was held, as an EXIF orientation from 1 to 8 - 1, upright,
+/// if the image does not say.
+fn orientation(data: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+/// Decode an image and turn it the way it was meant to be seen.
+pub fn upright(data: &[u8]) -> Result<DynamicImage, String> {
+    let image =
+        image_crate::load_from_memory(data).map_err(|e| format!("cannot read photo: {}", e))?;
+    Ok(match orientation(data) {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    })
+}
+
+/// A JPEG of an image shrunk to fit in a `pixels` square. Images that
+/// already fit are not enlarged.
+fn thumbnail(image: &DynamicImage, pixels: u32) -> Result<Vec<u8>, String> {
+    let scaled = if image.width() > pixels || image.height() > pixels {
+        image.thumbnail(pixels, pixels)
+    } else {
+        image.clone()
+    };
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(scaled.to_rgb8())
+        .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(85))
+        .map_err(|e| format!("cannot encode thumbnail: {}", e))?;
+    Ok(jpeg)
+}
+
+/// A difference hash of an image - one bit for each pair of neighbouring
+/// pixels in a 9 by 8 greyscale copy, set where the right one is brighter.
+/// Images that look alike have hashes that differ in few bits, whatever
+/// their size, brightness or compression.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x + 1, y).0[0] > small.get_pixel(x, y).0[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// Store a photo of an entity, as its primary photo if `primary` is set or
+/// it is the first. Returns the new photo's id.
+pub async fn insert(
+    conn: &mut SqliteConnection,
+    blobs: &BlobStore,
+    kind: PhotoKind,
+    entity_id: i64,
+    photo: Prepared,
+    caption: Option<&str>,
+    primary: bool,
+) -> Result<i64, StoreError> {
+    let hash = blobs.put(&photo.data).await?;
+    let mut thumbnails = Vec::new();
+    for (size, data) in &photo.thumbnails {
+        thumbnails.push((*size, blobs.put(data).await?));
+    }
+
+    let kind = kind.as_str();
+    let mut tx = conn.begin().await?;
+    let has_primary = sqlx::query!(
+        "SELECT id FROM photo WHERE kind = ? AND entity_id = ? AND is_primary",
+        kind,
+        entity_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .is_some();
+    let primary = primary || !has_primary;
+    if primary {
+        sqlx::query!(
+            "UPDATE photo SET is_primary = FALSE WHERE kind = ? AND entity_id = ?",
+            kind,
+            entity_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let width = photo.dimensions.map(|(width, _)| width as i64);
+    let height = photo.dimensions.map(|(_, height)| height as i64);
+    let id = sqlx::query!(
+        "INSERT INTO photo (kind, entity_id, hash, content_type, caption, is_primary, width, height)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        kind,
+        entity_id,
+        hash,
+        photo.content_type,
+        caption,
+        primary,
+        width,
+        height
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
+    for (size, hash) in thumbnails {
+        sqlx::query!(
+            "INSERT INTO photo_thumbnail (photo_id, size, hash) VALUES (?, ?, ?)",
+            id,
+            size,
+            hash
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    if let Some(dhash) = photo.dhash {
+        // stored as the signed integer SQLite has
+        let dhash = dhash as i64;
+        sqlx::query!(
+            "INSERT INTO photo_hash (photo_id, dhash) VALUES (?, ?)",
+            id,
+            dhash
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// The photo shown for an entity by default - its primary photo, or its
+/// oldest if none is marked.
+pub async fn primary(
+    conn: &mut SqliteConnection,
+    kind: PhotoKind,
+    entity_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    let kind = kind.as_str();
+    let photo = sqlx::query!(
+        r#"SELECT id AS "id!" FROM photo WHERE kind = ? AND entity_id = ?
+        ORDER BY is_primary DESC, id LIMIT 1"#,
+        kind,
+        entity_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(photo.map(|photo| photo.id))
+}
+
+/// Delete a photo. If it was its entity's primary photo, the oldest one
+/// left takes its place.
+pub async fn remove(conn: &mut SqliteConnection, id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let photo = match sqlx::query!(
+        "SELECT kind, entity_id, is_primary FROM photo WHERE id = ?",
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    {
+        Some(photo) => photo,
+        None => return Ok(false),
+    };
+    sqlx::query!("DELETE FROM photo WHERE id = ?", id)
+        .execute(&mut tx)
+        .await?;
+    if photo.is_primary {
+        sqlx::query!(
+            "UPDATE photo SET is_primary = TRUE WHERE id = (
+                SELECT id FROM photo WHERE kind = ?1 AND entity_id = ?2 ORDER BY id LIMIT 1
+            )",
+            photo.kind,
+            photo.entity_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+async fn info(conn: &mut SqliteConnection, id: i64) -> Result<Option<PhotoInfo>, sqlx::Error> {
+    sqlx::query_as!(
+        PhotoInfo,
+        r#"SELECT id AS "id!", caption, is_primary AS "primary: bool", content_type, width, height,
+            created_at
+        FROM photo WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+/// Add a photo to an entity's gallery from a multipart form. Its thumbnails
+/// are made straight away, so galleries never wait for them.
+#[post("/<kind>/<id>/photos", data = "<upload>", rank = 3)]
+pub async fn create(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    kind: PhotoKind,
+    id: i64,
+    upload: Form<PhotoUpload>,
+) -> Result<Option<Result<Created<Json<PhotoInfo>>, UploadError>>, StoreError> {
+    if !kind.exists(&mut db, id).await? {
+        return Ok(None);
+    }
+    let PhotoUpload {
+        photo,
+        caption,
+        primary,
+    } = upload.into_inner();
+    let prepared = match crate::render::run(state, move |_| Prepared::new(photo)).await {
+        Ok(prepared) => prepared,
+        Err(e) => return Ok(Some(Err(e.into()))),
+    };
+    let photo_id = insert(
+        &mut db,
+        &state.blobs,
+        kind,
+        id,
+        prepared,
+        caption.as_deref(),
+        primary,
+    )
+    .await?;
+
+    let info = info(&mut db, photo_id).await?;
+    Ok(info.map(|info| Ok(Created::new(format!("/photo/{}", photo_id)).body(Json(info)))))
+}
+
+/// An entity's photos, the primary one first and then oldest first.
+#[get("/<kind>/<id>/photos", rank = 3)]
+pub async fn list(
+    mut db: Connection<Db>,
+    kind: PhotoKind,
+    id: i64,
+) -> Result<Option<Json<Vec<PhotoInfo>>>> {
+    if !kind.exists(&mut db, id).await? {
+        return Ok(None);
+    }
+    let kind = kind.as_str();
+    let photos = sqlx::query_as!(
+        PhotoInfo,
+        r#"SELECT id AS "id!", caption, is_primary AS "primary: bool", content_type, width, height,
+            created_at
+        FROM photo WHERE kind = ? AND entity_id = ? ORDER BY is_primary DESC, id"#,
+        kind,
+        id
+    )
+    .fetch(&mut *db)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(Some(Json(photos)))
+}
+
+/// A photo's image or a thumbnail of it, which never change once made.
+/// Thumbnails of photos stored before galleries are made the first time
+/// they are asked for.
+async fn image(
+    db: &mut SqliteConnection,
+    state: &Arc<AppState>,
+    id: i64,
+    size: Option<ThumbnailSize>,
+) -> Result<Option<Result<(ContentType, Vec<u8>), UploadError>>, StoreError> {
+    let photo = match sqlx::query!(
+        r#"SELECT hash AS "hash!", content_type FROM photo WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(&mut *db)
+    .await?
+    {
+        Some(photo) => photo,
+        None => return Ok(None),
+    };
+    let size = match size {
+        Some(size) => size.pixels(),
+        None => {
+            let content_type =
+                ContentType::parse_flexible(&photo.content_type).unwrap_or(ContentType::Binary);
+            let data = state.blobs.get(&photo.hash).await?;
+            return Ok(Some(Ok((content_type, data))));
+        }
+    };
+
+    let stored = sqlx::query!(
+        r#"SELECT hash AS "hash!" FROM photo_thumbnail WHERE photo_id = ? AND size = ?"#,
+        id,
+        size
+    )
+    .fetch_optional(&mut *db)
+    .await?;
+    if let Some(stored) = stored {
+        let data = state.blobs.get(&stored.hash).await?;
+        return Ok(Some(Ok((ContentType::JPEG, data))));
+    }
+
+    let data = state.blobs.get(&photo.hash).await?;
+    let made = crate::render::run(state, move |_| thumbnail(&upright(&data)?, size)).await;
+    let made = match made {
+        Ok(made) => made,
+        Err(e) => return Ok(Some(Err(e.into()))),
+    };
+    let hash = state.blobs.put(&made).await?;
+    sqlx::query!(
+        "INSERT OR IGNORE INTO photo_thumbnail (photo_id, size, hash) VALUES (?, ?, ?)",
+        id,
+        size,
+        hash
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(Some(Ok((ContentType::JPEG, made))))
+}
+
+/// A photo by id - the image as it was uploaded, or with `size` a JPEG
+/// thumbnail of it.
+#[get("/photo/<id>?<size>")]
+pub async fn read(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    size: Option<ThumbnailSize>,
+) -> Result<Option<Result<PhotoData, UploadError>>, StoreError> {
+    let image = image(&mut db, state, id, size).await?;
+    Ok(image.map(|image| {
+        image.map(|body| PhotoData {
+            body,
+            cache_control: Header::new("Cache-Control", "max-age=31536000, immutable"),
+        })
+    }))
+}
+
+/// Change a photo's caption, or make it its entity's primary photo.
+/// Unmarking the primary photo leaves the oldest one shown by default.
+#[put("/photo/<id>", data = "<photo>")]
+pub async fn update(
+    mut db: Connection<Db>,
+    id: i64,
+    photo: Json<PutPhoto>,
+) -> Result<Option<Json<PhotoInfo>>> {
+    let mut tx = db.begin().await?;
+    let existing = match sqlx::query!("SELECT kind, entity_id FROM photo WHERE id = ?", id)
+        .fetch_optional(&mut tx)
+        .await?
+    {
+        Some(existing) => existing,
+        None => return Ok(None),
+    };
+    if photo.primary {
+        sqlx::query!(
+            "UPDATE photo SET is_primary = FALSE WHERE kind = ? AND entity_id = ?",
+            existing.kind,
+            existing.entity_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    sqlx::query!(
+        "UPDATE photo SET caption = ?, is_primary = ? WHERE id = ?",
+        photo.caption,
+        photo.primary,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(info(&mut db, id).await?.map(Json))
+}
+
+#[delete("/photo/<id>")]
+pub async fn delete(mut db: Connection<Db>, id: i64) -> Result<Option<()>> {
+    Ok(remove(&mut db, id).await?.then_some(()))
+}
+
+/// Add a photo from a multipart form as an entity's primary photo. The
+/// photo routes are ranked behind `read_qr` and the like, which they would
+/// otherwise collide with.
+#[post("/<kind>/<id>/photo", data = "<upload>", rank = 3)]
+pub async fn upload_primary(
+    db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    kind: PhotoKind,
+    id: i64,
+    mut upload: Form<PhotoUpload>,
+) -> Result<Option<Result<(), UploadError>>, StoreError> {
+    upload.primary = true;
+    let created = create(db, state, kind, id, upload).await?;
+    Ok(created.map(|created| created.map(|_| ())))
+}
+
+/// An entity's primary photo - see `read`.
+#[get("/<kind>/<id>/photo?<size>", rank = 3)]
+pub async fn read_primary(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    kind: PhotoKind,
+    id: i64,
+    size: Option<ThumbnailSize>,
+) -> Result<Option<Result<PhotoData, UploadError>>, StoreError> {
+    let photo_id = match primary(&mut db, kind, id).await? {
+        Some(photo_id) => photo_id,
+        None => return Ok(None),
+    };
+    let image = image(&mut db, state, photo_id, size).await?;
+    // which photo is primary changes
+    Ok(image.map(|image| {
+        image.map(|body| PhotoData {
+            body,
+            cache_control: Header::new("Cache-Control", "no-cache"),
+        })
+    }))
+}
+
+/// Delete an entity's primary photo, leaving the next one in its place.
+#[delete("/<kind>/<id>/photo", rank = 3)]
+pub async fn delete_primary(
+    mut db: Connection<Db>,
+    kind: PhotoKind,
+    id: i64,
+) -> Result<Option<()>> {
+    match primary(&mut db, kind, id).await? {
+        Some(photo_id) => Ok(remove(&mut db, photo_id).await?.then_some(())),
+        None => Ok(None),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for Photo {
+    async fn from_data(field: DataField<'r, '_>) -> form::Result<'r, Self> {
+        let limit = field
+            .request
+            .limits()
+            .get("photo")
+            .unwrap_or_else(|| 10.mebibytes());
+        let data = field.data.open(limit).into_bytes().await?;
+        if !data.is_complete() {
+            return Err((None, Some(limit)).into());
+        }
+        let data = data.into_inner();
+        let content_type = content_type(&data)
+            .ok_or_else(|| form::Error::validation("photos must be PNG, JPEG, GIF or WebP"))?;
+        Ok(Photo { data, content_type })
+    }
+}
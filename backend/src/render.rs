@@ -0,0 +1,234 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use printpdf::image_crate::ImageOutputFormat;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::sync::Semaphore;
+use rocket_db_pools::sqlx;
+
+use crate::config::LabelConfig;
+use crate::layout::LabelData;
+use crate::template::LabelTemplate;
+use crate::thermal::ThermalFormat;
+use crate::AppState;
+
+/// What labels are rendered as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Output {
+    /// One label as an image
+    Png,
+    /// One label as a drawing
+    Svg,
+    /// Sheets of labels
+    Pdf,
+    Thermal(ThermalFormat),
+}
+
+/// Why labels could not be rendered.
+#[derive(Debug, Responder)]
+pub enum RenderError {
+    /// The labels cannot be drawn as asked - e.g. there is more to encode
+    /// than the code has room for
+    #[response(status = 400)]
+    Invalid(String),
+    /// Drawing them crashed
+    #[response(status = 500)]
+    Failed(String),
+}
+
+/// Rendered labels, sent with an ETag so clients can skip downloading them
+/// again while they are unchanged.
+#[derive(Debug, Clone)]
+pub struct Rendered {
+    pub content_type: ContentType,
+    pub etag: String,
+    pub body: Arc<[u8]>,
+}
+
+impl<'r> Responder<'r, 'static> for Rendered {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let unchanged = request
+            .headers()
+            .get("If-None-Match")
+            .flat_map(|tags| tags.split(','))
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == self.etag);
+
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", self.etag.clone())
+            .raw_header("Cache-Control", "no-cache");
+        if unchanged {
+            response.status(Status::NotModified);
+        } else {
+            response
+                .header(self.content_type)
+                .sized_body(self.body.len(), Cursor::new(self.body));
+        }
+        response.ok()
+    }
+}
+
+/// Renders labels on the blocking thread pool, a few at a time so a burst
+/// of requests for big sheets cannot starve everything else, and keeps the
+/// most recently rendered ones, up to a count and a total size.
+pub struct Renderer {
+    permits: Semaphore,
+    cache: Mutex<Cache>,
+    cache_size: usize,
+    cache_bytes: usize,
+}
+
+#[derive(Default)]
+struct Cache {
+    rendered: HashMap<u64, Rendered>,
+    /// Keys oldest first, for evicting
+    order: VecDeque<u64>,
+    /// The size of every body kept
+    bytes: usize,
+}
+
+impl Renderer {
+    pub fn new(config: &LabelConfig) -> Self {
+        let limit = config
+            .max_renders
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |cpus| cpus.get()));
+        Renderer {
+            permits: Semaphore::new(limit),
+            cache: Mutex::new(Cache::default()),
+            cache_size: config.cache_size,
+            cache_bytes: config.cache_bytes.as_u64().try_into().unwrap_or(usize::MAX),
+        }
+    }
+
+    fn cached(&self, key: u64) -> Option<Rendered> {
+        self.cache.lock().unwrap().rendered.get(&key).cloned()
+    }
+
+    fn insert(&self, key: u64, rendered: Rendered) {
+        if self.cache_size == 0 || rendered.body.len() > self.cache_bytes {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        cache.bytes += rendered.body.len();
+        match cache.rendered.insert(key, rendered) {
+            Some(replaced) => cache.bytes -= replaced.body.len(),
+            None => cache.order.push_back(key),
+        }
+        while cache.order.len() > self.cache_size || cache.bytes > self.cache_bytes {
+            if let Some(oldest) = cache.order.pop_front() {
+                if let Some(evicted) = cache.rendered.remove(&oldest) {
+                    cache.bytes -= evicted.body.len();
+                }
+            }
+        }
+    }
+}
+
+/// Run `render` on the blocking thread pool once the renderer has room.
+pub async fn run<T, F>(state: &Arc<AppState>, render: F) -> Result<T, RenderError>
+where
+    F: FnOnce(&AppState) -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    let _permit = state
+        .renderer
+        .permits
+        .acquire()
+        .await
+        .map_err(|e| RenderError::Failed(e.to_string()))?;
+    let shared = Arc::clone(state);
+    rocket::tokio::task::spawn_blocking(move || render(&shared))
+        .await
+        .map_err(|e| RenderError::Failed(format!("rendering failed: {}", e)))?
+        .map_err(RenderError::Invalid)
+}
+
+/// Identifies what rendering `labels` would produce - each label's code and
+/// text, and the template they are drawn on.
+fn key(state: &AppState, labels: &[LabelData], template: &LabelTemplate, output: Output) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    output.hash(&mut hasher);
+    // templates hold lengths in floats, which do not hash
+    rocket::serde::json::to_string(template)
+        .unwrap()
+        .hash(&mut hasher);
+    for label in labels {
+        label.hash(&mut hasher);
+        crate::util::qr_payload(state, label).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Render labels for a single entity or a whole list of them, or reuse
+/// them if they have been rendered the same way before. Images and
+/// drawings are of the first label only.
+pub async fn labels(
+    state: &Arc<AppState>,
+    labels: Vec<LabelData>,
+    template: &LabelTemplate,
+    output: Output,
+) -> Result<Rendered, RenderError> {
+    let key = key(state, &labels, template, output);
+    if let Some(rendered) = state.renderer.cached(key) {
+        return Ok(rendered);
+    }
+
+    let template = template.clone();
+    let (content_type, body) = run(state, move |state| match output {
+        Output::Png => {
+            let image = crate::util::generate_qr_label(state, &labels[0], &template)?;
+            let mut png = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+                .map_err(|e| format!("cannot encode label: {}", e))?;
+            Ok((ContentType::PNG, png))
+        }
+        Output::Svg => crate::vector::label_svg(state, &labels[0], &template)
+            .map(|svg| (ContentType::SVG, svg.into_bytes())),
+        Output::Pdf => crate::util::generate_qr_pdf(state, &labels, &template, 0)
+            .map(|pdf| (ContentType::PDF, pdf)),
+        Output::Thermal(format) => {
+            let images = labels
+                .iter()
+                .map(|label| crate::util::generate_qr_label(state, label, &template))
+                .collect::<Result<Vec<_>, _>>()?;
+            crate::thermal::encode(&images, &template, format)
+        }
+    })
+    .await?;
+
+    let rendered = Rendered {
+        content_type,
+        etag: format!("\"{:016x}\"", key),
+        body: body.into(),
+    };
+    state.renderer.insert(key, rendered.clone());
+    Ok(rendered)
+}
+
+/// The label of one entity, as its `read_qr`, `read_svg` and `read_thermal`
+/// routes serve it - given what its `label_data` found. Nothing if the
+/// template or the entity does not exist.
+pub async fn label(
+    state: &Arc<AppState>,
+    label: Result<LabelData, sqlx::Error>,
+    template: Option<&str>,
+    output: Output,
+) -> Result<Option<Result<Rendered, RenderError>>, response::Debug<sqlx::Error>> {
+    let template = match crate::template::find(state, template) {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let label = match label {
+        Ok(label) => label,
+        Err(sqlx::Error::RowNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(labels(state, vec![label], template, output).await))
+}
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::response::Debug;
+use rocket::State;
+use rocket_db_pools::{sqlx, Connection};
+
+use crate::lookup::Lookup;
+use crate::upload::UploadError;
+use crate::AppState;
+use crate::Db;
+
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+/// What was found in a scanned photo.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Scan {
+    /// Everything a QR code in the photo refers to, in the order the codes
+    /// were found
+    pub found: Vec<Lookup>,
+    /// QR codes that did not refer to anything we know of
+    pub unknown: Vec<String>,
+}
+
+/// The contents of every QR code that can be read in an image, without
+/// repeats.
+pub fn decode(image: &[u8]) -> Result<Vec<String>, String> {
+    let image = printpdf::image_crate::load_from_memory(image)
+        .map_err(|e| format!("cannot read image: {}", e))?
+        .to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+
+    let mut codes = Vec::new();
+    for grid in prepared.detect_grids() {
+        // a code that was found but cannot be read is skipped, not an error
+        if let Ok((_, content)) = grid.decode() {
+            if !codes.contains(&content) {
+                codes.push(content);
+            }
+        }
+    }
+    Ok(codes)
+}
+
+/// Read every QR code in an uploaded photo - e.g. from a webcam, or of a
+/// whole shelf - and look up what each refers to, as `lookup::lookup` does.
+/// The image is sent as the request body in any common format, and decoded
+/// on the blocking thread pool, as labels are rendered.
+#[post("/scan", data = "<data>")]
+pub async fn scan(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    data: Data<'_>,
+    limits: &Limits,
+) -> Result<Result<Json<Scan>, UploadError>, Debug<sqlx::Error>> {
+    let limit = limits.get("scan").unwrap_or_else(|| 10.mebibytes());
+    let image = match crate::upload::read(data, limit).await {
+        Ok(image) => image,
+        Err(e) => return Ok(Err(e)),
+    };
+    let codes = match crate::render::run(state, move |_| decode(&image)).await {
+        Ok(codes) => codes,
+        Err(e) => return Ok(Err(e.into())),
+    };
+
+    let mut scan = Scan::default();
+    for code in codes {
+        match crate::lookup::resolve(&mut db, state, &code).await? {
+            Some(found) => scan.found.push(found),
+            None => scan.unknown.push(code),
+        }
+    }
+    Ok(Ok(Json(scan)))
+}
@@ -0,0 +1,168 @@
+use crate::rocket::futures::TryFutureExt;
+use rand::Rng;
+use rocket::response::Redirect;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::Db;
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// Crockford's base 32 - no I, L, O or U, so codes survive being read out or
+/// copied by hand.
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Characters after the dash. Codes get longer if this length fills up.
+const LENGTH: usize = 4;
+
+/// A random code for an entity of `kind` - its initial, a dash, and `length`
+/// random characters, e.g. `C-7K2P` for a container.
+fn generate(kind: &str, length: usize) -> String {
+    let mut rng = rand::thread_rng();
+    let body: String = (0..length)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", kind[..1].to_uppercase(), body)
+}
+
+/// Put a code typed or read by hand into its canonical form, reading
+/// lowercase as uppercase and the letters Crockford leaves out as the digits
+/// they look like.
+pub fn normalize(code: &str) -> Option<String> {
+    let (prefix, body) = code.trim().split_once('-')?;
+    if prefix.len() != 1 || body.is_empty() {
+        return None;
+    }
+    let body = body
+        .to_uppercase()
+        .chars()
+        .map(|c| match c {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect::<String>();
+    if !body.bytes().all(|b| ALPHABET.contains(&b)) {
+        return None;
+    }
+    Some(format!("{}-{}", prefix.to_uppercase(), body))
+}
+
+/// The code of an entity, if it has been given one.
+pub async fn get(
+    conn: &mut SqliteConnection,
+    kind: &str,
+    id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query!(
+        "SELECT code FROM short_code WHERE kind = ? AND entity_id = ?",
+        kind,
+        id
+    )
+    .fetch_optional(conn)
+    .map_ok(|r| r.map(|r| r.code))
+    .await
+}
+
+/// The code of an entity, giving it one if it has none yet. Codes never
+/// change once given.
+pub async fn assign(
+    conn: &mut SqliteConnection,
+    kind: &str,
+    id: i64,
+) -> Result<String, sqlx::Error> {
+    for attempt in 0.. {
+        if let Some(code) = get(&mut *conn, kind, id).await? {
+            return Ok(code);
+        }
+        let code = generate(kind, LENGTH + attempt / 8);
+        let result = sqlx::query!(
+            "INSERT OR IGNORE INTO short_code (code, kind, entity_id) VALUES (?, ?, ?)",
+            code,
+            kind,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+        if result.rows_affected() == 1 {
+            return Ok(code);
+        }
+    }
+    unreachable!()
+}
+
+/// Give every container, item and unit without a code one - those created
+/// before codes were. Run at startup, after the migrations, so that reading
+/// a code never has to write one.
+pub async fn assign_missing(conn: &mut SqliteConnection) -> Result<u64, sqlx::Error> {
+    let mut missing = Vec::new();
+    for r in sqlx::query!(
+        r#"SELECT id AS "id!" FROM container WHERE id NOT IN
+        (SELECT entity_id FROM short_code WHERE kind = 'container')"#
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        missing.push(("container", r.id));
+    }
+    for r in sqlx::query!(
+        r#"SELECT id AS "id!" FROM item WHERE id NOT IN
+        (SELECT entity_id FROM short_code WHERE kind = 'item')"#
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        missing.push(("item", r.id));
+    }
+    for r in sqlx::query!(
+        r#"SELECT id AS "id!" FROM unit WHERE id NOT IN
+        (SELECT entity_id FROM short_code WHERE kind = 'unit')"#
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        missing.push(("unit", r.id));
+    }
+
+    for (kind, id) in &missing {
+        assign(&mut *conn, kind, *id).await?;
+    }
+    Ok(missing.len() as u64)
+}
+
+/// The kind and id of the entity a code belongs to.
+pub async fn resolve(
+    conn: &mut SqliteConnection,
+    code: &str,
+) -> Result<Option<(String, i64)>, sqlx::Error> {
+    let code = match normalize(code) {
+        Some(code) => code,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        "SELECT kind, entity_id FROM short_code WHERE code = ?",
+        code
+    )
+    .fetch_optional(conn)
+    .map_ok(|r| r.map(|r| (r.kind, r.entity_id)))
+    .await
+}
+
+/// Find the entity a scanned code refers to, whether it is a bare code or a
+/// `/q/<code>` link printed under any domain.
+pub async fn find(db: &mut Connection<Db>, scanned: &str) -> Option<(String, i64)> {
+    let scanned = scanned.trim();
+    let code = match scanned.rsplit_once("/q/") {
+        Some((_, code)) => code.trim_end_matches('/'),
+        None => scanned,
+    };
+    resolve(db, code).await.ok().flatten()
+}
+
+/// Where label codes point. Redirects to the entity on whatever host the
+/// label was scanned against, so labels keep working after a domain move.
+#[get("/q/<code>")]
+pub async fn redirect(mut db: Connection<Db>, code: &str) -> Result<Option<Redirect>> {
+    let entity = resolve(&mut db, code).await?;
+    Ok(entity.map(|(kind, id)| Redirect::to(format!("/{}/{}", kind, id))))
+}
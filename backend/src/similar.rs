@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+
+use crate::blob::StoreError;
+use crate::upload::UploadError;
+use crate::{AppState, Db};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// How many of the 64 bits of a perceptual hash may differ for photos to
+/// count as alike, unless asked otherwise.
+const MAX_DISTANCE: u32 = 12;
+/// How many items a search returns, unless asked otherwise.
+const LIMIT: usize = 10;
+
+/// An item with a photo that looks like the one searched with.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SimilarItem {
+    pub item_id: i64,
+    pub name: String,
+    /// The item's photo that looks most alike
+    pub photo_id: i64,
+    /// How many bits of the photos' perceptual hashes differ - 0 for photos
+    /// that look the same
+    pub distance: u32,
+    /// Where the item is kept - e.g. "1000 Washington Street / Toolchest"
+    pub locations: Vec<String>,
+}
+
+/// Work out the perceptual hashes of item photos that do not have one yet -
+/// those stored before hashes were, or given as JSON.
+async fn hash_missing(
+    conn: &mut SqliteConnection,
+    state: &Arc<AppState>,
+) -> Result<Result<(), UploadError>, StoreError> {
+    let photos = sqlx::query!(
+        r#"SELECT id AS "id!", hash AS "hash!" FROM photo
+        WHERE kind = 'item' AND id NOT IN (SELECT photo_id FROM photo_hash)"#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for photo in photos {
+        let data = state.blobs.get(&photo.hash).await?;
+        let dhash = crate::render::run(state, move |_| {
+            Ok(crate::photo::upright(&data)
+                .ok()
+                .map(|image| crate::photo::dhash(&image) as i64))
+        })
+        .await;
+        let dhash = match dhash {
+            Ok(dhash) => dhash,
+            Err(e) => return Ok(Err(e.into())),
+        };
+        sqlx::query!(
+            "INSERT OR IGNORE INTO photo_hash (photo_id, dhash) VALUES (?, ?)",
+            photo.id,
+            dhash
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(Ok(()))
+}
+
+/// The paths of the containers an item is kept in.
+async fn locations(conn: &mut SqliteConnection, item_id: i64) -> Result<Vec<String>, sqlx::Error> {
+    let containers = sqlx::query!(
+        "SELECT DISTINCT container_id FROM item_location WHERE item_id = ? ORDER BY container_id",
+        item_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut locations = Vec::new();
+    for container in containers {
+        let path = crate::container::path(&mut *conn, container.container_id).await?;
+        locations.push(path.join(" / "));
+    }
+    Ok(locations)
+}
+
+/// Find items whose photos look like an uploaded photo - e.g. of an
+/// unlabelled part found on the bench, to see what it is and where it goes.
+/// The image is sent as the request body in any common format, and may be
+/// taken at any quarter turn. Items come closest first.
+#[post("/item/search-by-photo?<max_distance>&<limit>", data = "<data>")]
+pub async fn search(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    data: Data<'_>,
+    limits: &Limits,
+    max_distance: Option<u32>,
+    limit: Option<usize>,
+) -> Result<Result<Json<Vec<SimilarItem>>, UploadError>, StoreError> {
+    let size_limit = limits.get("photo").unwrap_or_else(|| 10.mebibytes());
+    let image = match crate::upload::read(data, size_limit).await {
+        Ok(image) => image,
+        Err(e) => return Ok(Err(e)),
+    };
+    let hashes = crate::render::run(state, move |_| {
+        let image = crate::photo::upright(&image)?;
+        Ok([
+            crate::photo::dhash(&image),
+            crate::photo::dhash(&image.rotate90()),
+            crate::photo::dhash(&image.rotate180()),
+            crate::photo::dhash(&image.rotate270()),
+        ])
+    })
+    .await;
+    let hashes = match hashes {
+        Ok(hashes) => hashes,
+        Err(e) => return Ok(Err(e.into())),
+    };
+    if let Err(e) = hash_missing(&mut db, state).await? {
+        return Ok(Err(e));
+    }
+
+    let photos = sqlx::query!(
+        r#"SELECT photo.id AS "id!", photo.entity_id, photo_hash.dhash AS "dhash!"
+        FROM photo JOIN photo_hash ON photo_hash.photo_id = photo.id
+        WHERE photo.kind = 'item' AND photo_hash.dhash IS NOT NULL"#
+    )
+    .fetch_all(&mut *db)
+    .await?;
+    let max_distance = max_distance.unwrap_or(MAX_DISTANCE);
+    // the closest photo of each item
+    let mut closest: HashMap<i64, (u32, i64)> = HashMap::new();
+    for photo in photos {
+        let distance = hashes
+            .iter()
+            .map(|hash| (hash ^ photo.dhash as u64).count_ones())
+            .min()
+            .unwrap();
+        if distance > max_distance {
+            continue;
+        }
+        let entry = closest
+            .entry(photo.entity_id)
+            .or_insert((distance, photo.id));
+        if (distance, photo.id) < *entry {
+            *entry = (distance, photo.id);
+        }
+    }
+    let mut closest: Vec<(u32, i64, i64)> = closest
+        .into_iter()
+        .map(|(item_id, (distance, photo_id))| (distance, item_id, photo_id))
+        .collect();
+    closest.sort();
+    closest.truncate(limit.unwrap_or(LIMIT));
+
+    let mut items = Vec::new();
+    for (distance, item_id, photo_id) in closest {
+        let item = sqlx::query!("SELECT name FROM item WHERE id = ?", item_id)
+            .fetch_one(&mut *db)
+            .await?;
+        items.push(SimilarItem {
+            item_id,
+            name: item.name,
+            photo_id,
+            distance,
+            locations: locations(&mut db, item_id).await?,
+        });
+    }
+    Ok(Ok(Json(items)))
+}
@@ -0,0 +1,190 @@
+use barcoders::sym::code128::Code128;
+use datamatrix::{DataMatrix, SymbolList};
+use printpdf::image_crate::{GrayImage, Luma};
+use qrcode::{EcLevel as QrEcLevel, QrCode, Version};
+use rocket::serde::{Deserialize, Serialize};
+
+/// The kind of code printed on a label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Symbology {
+    #[default]
+    Qr,
+    /// Smaller than QR, but only holds short payloads
+    MicroQr,
+    DataMatrix,
+    /// A linear barcode, for scanners that only read those
+    Code128,
+}
+
+/// How much of a code can be damaged and still scan. Data Matrix always
+/// uses its own fixed error correction and Code128 has none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum EcLevel {
+    #[default]
+    Low,
+    Medium,
+    Quartile,
+    High,
+}
+
+impl Symbology {
+    pub fn is_linear(&self) -> bool {
+        *self == Symbology::Code128
+    }
+
+    /// Check that the symbology can correct errors at `ec_level`.
+    pub fn validate(&self, ec_level: EcLevel) -> Result<(), String> {
+        if *self == Symbology::MicroQr && ec_level == EcLevel::High {
+            return Err("Micro QR codes do not support high error correction".into());
+        }
+        Ok(())
+    }
+}
+
+/// An encoded code - rows of modules, `true` where dark. Linear codes have
+/// a single row, stretched to whatever height they are drawn at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub modules: Vec<Vec<bool>>,
+    /// Light modules needed around the code for it to scan
+    pub quiet_zone: usize,
+}
+
+/// A rectangle to draw a symbol into, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub x: f32,
+    pub y: f32,
+    pub module_width: f32,
+    pub module_height: f32,
+}
+
+impl Symbol {
+    pub fn encode(payload: &str, symbology: Symbology, ec_level: EcLevel) -> Result<Self, String> {
+        let qr_ec_level = match ec_level {
+            EcLevel::Low => QrEcLevel::L,
+            EcLevel::Medium => QrEcLevel::M,
+            EcLevel::Quartile => QrEcLevel::Q,
+            EcLevel::High => QrEcLevel::H,
+        };
+        let from_qr = |code: QrCode, quiet_zone| {
+            let modules = code
+                .to_colors()
+                .chunks(code.width())
+                .map(|row| row.iter().map(|&c| c == qrcode::Color::Dark).collect())
+                .collect();
+            Symbol {
+                modules,
+                quiet_zone,
+            }
+        };
+
+        match symbology {
+            Symbology::Qr => {
+                let code = QrCode::with_error_correction_level(payload, qr_ec_level)
+                    .map_err(|e| format!("cannot encode {:?} as a QR code: {}", payload, e))?;
+                // one module of quiet zone, as the original labels had
+                Ok(from_qr(code, 1))
+            }
+            Symbology::MicroQr => (1..=4)
+                .find_map(|version| {
+                    QrCode::with_version(payload, Version::Micro(version), qr_ec_level).ok()
+                })
+                .map(|code| from_qr(code, 2))
+                .ok_or(format!("{:?} is too long for a Micro QR code", payload)),
+            Symbology::DataMatrix => {
+                let code = DataMatrix::encode_str(payload, SymbolList::default()).map_err(|e| {
+                    format!("cannot encode {:?} as a Data Matrix: {:?}", payload, e)
+                })?;
+                let bitmap = code.bitmap();
+                let mut modules = vec![vec![false; bitmap.width()]; bitmap.height()];
+                for (x, y) in bitmap.pixels() {
+                    modules[y][x] = true;
+                }
+                Ok(Symbol {
+                    modules,
+                    quiet_zone: 1,
+                })
+            }
+            Symbology::Code128 => {
+                // start in character set B, which covers printable ASCII
+                let code = Code128::new(format!("Ɓ{}", payload))
+                    .map_err(|e| format!("cannot encode {:?} as Code128: {}", payload, e))?;
+                Ok(Symbol {
+                    modules: vec![code.encode().into_iter().map(|bit| bit == 1).collect()],
+                    quiet_zone: 10,
+                })
+            }
+        }
+    }
+
+    fn columns(&self) -> usize {
+        self.modules[0].len()
+    }
+
+    /// Where to draw the symbol to fill as much of a `width` by `height` box
+    /// as it can, centred, with `integer` modules that are a whole number of
+    /// pixels for crisp bitmaps. 2D symbols keep their modules square.
+    pub fn place(&self, width: f32, height: f32, integer: bool) -> Result<Placement, String> {
+        let round = |size: f32| if integer { size.floor() } else { size };
+        let module_width = round(width / (self.columns() + 2 * self.quiet_zone) as f32);
+        let module_height = if self.modules.len() == 1 {
+            height
+        } else {
+            module_width.min(round(
+                height / (self.modules.len() + 2 * self.quiet_zone) as f32,
+            ))
+        };
+        let module_width = if self.modules.len() == 1 {
+            module_width
+        } else {
+            module_height
+        };
+        if module_width < 1.0 {
+            return Err("the label is too small for its code".into());
+        }
+
+        let drawn_width = module_width * self.columns() as f32;
+        let drawn_height = module_height * self.modules.len() as f32;
+        Ok(Placement {
+            x: round((width - drawn_width) / 2.0),
+            y: round((height - drawn_height) / 2.0),
+            module_width,
+            module_height,
+        })
+    }
+
+    /// Runs of dark modules along each row, as (row, first column, length).
+    pub fn dark_runs(&self) -> Vec<(usize, usize, usize)> {
+        let mut runs = Vec::new();
+        for (row, dark) in self.modules.iter().enumerate() {
+            let mut column = 0;
+            while column < dark.len() {
+                let run = dark[column..].iter().take_while(|&&d| d).count();
+                if run > 0 {
+                    runs.push((row, column, run));
+                }
+                column += run.max(1);
+            }
+        }
+        runs
+    }
+
+    /// Draw the symbol dark on light, filling a `width` by `height` image.
+    pub fn to_image(&self, width: u32, height: u32) -> Result<GrayImage, String> {
+        let placement = self.place(width as f32, height as f32, true)?;
+        let mut image = GrayImage::from_pixel(width, height, Luma([255]));
+        for (row, column, run) in self.dark_runs() {
+            let x = placement.x + placement.module_width * column as f32;
+            let y = placement.y + placement.module_height * row as f32;
+            for py in y as u32..(y + placement.module_height) as u32 {
+                for px in x as u32..(x + placement.module_width * run as f32) as u32 {
+                    image.put_pixel(px, py, Luma([0]));
+                }
+            }
+        }
+        Ok(image)
+    }
+}
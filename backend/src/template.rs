@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+
+use printpdf::Mm;
+
+use crate::layout::{LabelField, LabelLayout, LabelStyle, TextPosition};
+use crate::AppState;
+
+/// The template used when none is asked for, unless `labels.default_template`
+/// says otherwise - the original yvonne layout of 8 by 9 one-inch labels on A4.
+pub const DEFAULT_TEMPLATE: &str = "yvonne-a4";
+
+/// A sheet of labels - the page, its margins and the grid of labels on it.
+/// All lengths are in millimetres.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LabelTemplate {
+    pub page_width: f64,
+    pub page_height: f64,
+    /// Distance from the top of the page to the top of the first row
+    pub margin_top: f64,
+    /// Distance from the left of the page to the left of the first column
+    pub margin_left: f64,
+    pub columns: usize,
+    pub rows: usize,
+    pub label_width: f64,
+    pub label_height: f64,
+    /// Horizontal space between neighbouring labels
+    #[serde(default)]
+    pub column_gap: f64,
+    /// Vertical space between neighbouring labels
+    #[serde(default)]
+    pub row_gap: f64,
+    /// Resolution labels are rendered at
+    #[serde(default = "default_dpi")]
+    pub dpi: f64,
+    #[serde(default)]
+    pub layout: LabelLayout,
+}
+
+fn default_dpi() -> f64 {
+    300.0
+}
+
+impl LabelTemplate {
+    /// The size of one label in pixels, at the template's resolution.
+    pub fn label_pixels(&self) -> (u32, u32) {
+        let pixels = |mm: f64| (mm / 25.4 * self.dpi).round() as u32;
+        (pixels(self.label_width), pixels(self.label_height))
+    }
+
+    pub fn labels_per_page(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// The bottom left corner of the `slot`th label on a page, counting
+    /// across then down, in PDF coordinates.
+    pub fn position(&self, slot: usize) -> (Mm, Mm) {
+        let column = (slot % self.columns) as f64;
+        let row = (slot / self.columns) as f64;
+        let x = self.margin_left + column * (self.label_width + self.column_gap);
+        let y = self.page_height
+            - self.margin_top
+            - row * (self.label_height + self.row_gap)
+            - self.label_height;
+        (Mm(x), Mm(y))
+    }
+
+    /// Check that the grid is non-empty and fits on the page, and that the
+    /// code can be printed as configured.
+    pub fn validate(&self) -> Result<(), String> {
+        let sizes = [
+            self.page_width,
+            self.page_height,
+            self.label_width,
+            self.label_height,
+            self.dpi,
+        ];
+        if sizes.iter().any(|l| l.is_nan() || *l <= 0.0) {
+            return Err("page and label sizes and resolution must be positive".into());
+        }
+        let spacing = [
+            self.margin_top,
+            self.margin_left,
+            self.column_gap,
+            self.row_gap,
+        ];
+        if spacing.iter().any(|l| l.is_nan() || *l < 0.0) {
+            return Err("margins and gaps must not be negative".into());
+        }
+        if self.columns == 0 || self.rows == 0 {
+            return Err("there must be at least one row and column".into());
+        }
+        self.layout.symbology.validate(self.layout.ec_level)?;
+        crate::layout::validate_qr_size(self.layout.qr_size)?;
+
+        // allow for rounding in published sheet dimensions
+        let tolerance = 0.5;
+        let columns = self.columns as f64;
+        let rows = self.rows as f64;
+        let width =
+            self.margin_left + columns * self.label_width + (columns - 1.0) * self.column_gap;
+        let height = self.margin_top + rows * self.label_height + (rows - 1.0) * self.row_gap;
+        if width > self.page_width + tolerance || height > self.page_height + tolerance {
+            return Err("labels do not fit on the page".into());
+        }
+
+        Ok(())
+    }
+}
+
+const A4: (f64, f64) = (210.0, 297.0);
+const LETTER: (f64, f64) = (215.9, 279.4);
+
+fn sheet(
+    (page_width, page_height): (f64, f64),
+    (margin_top, margin_left): (f64, f64),
+    (columns, rows): (usize, usize),
+    (label_width, label_height): (f64, f64),
+    (column_gap, row_gap): (f64, f64),
+    (text_position, fields): (TextPosition, &[LabelField]),
+) -> LabelTemplate {
+    LabelTemplate {
+        page_width,
+        page_height,
+        margin_top,
+        margin_left,
+        columns,
+        rows,
+        label_width,
+        label_height,
+        column_gap,
+        row_gap,
+        dpi: default_dpi(),
+        layout: LabelLayout {
+            fields: fields.to_vec(),
+            text_position,
+            ..LabelLayout::default()
+        },
+    }
+}
+
+/// Templates for some common label sheets. More can be defined under
+/// `label_templates` in `Rocket.toml`.
+pub fn builtin() -> HashMap<String, LabelTemplate> {
+    use LabelField::*;
+    use TextPosition::*;
+
+    // thermal printers only print dark, so their labels are printed on white
+    let thermal = |mut template: LabelTemplate| {
+        template.layout.style = LabelStyle::DarkOnLight;
+        template
+    };
+
+    HashMap::from([
+        (
+            DEFAULT_TEMPLATE.to_string(),
+            sheet(
+                A4,
+                (0.25, 0.76),
+                (8, 9),
+                (25.4, 31.75),
+                (0.76, 1.36),
+                (Below, &[Name]),
+            ),
+        ),
+        (
+            "avery-l7160".to_string(),
+            sheet(
+                A4,
+                (15.15, 7.25),
+                (3, 7),
+                (63.5, 38.1),
+                (2.5, 0.0),
+                (Right, &[Name, Path, Id]),
+            ),
+        ),
+        (
+            "avery-l7163".to_string(),
+            sheet(
+                A4,
+                (15.15, 4.65),
+                (2, 7),
+                (99.1, 38.1),
+                (2.5, 0.0),
+                (Right, &[Name, Path, Id, Quantity]),
+            ),
+        ),
+        (
+            "avery-l7651".to_string(),
+            sheet(
+                A4,
+                (10.7, 4.75),
+                (5, 13),
+                (38.1, 21.2),
+                (2.5, 0.0),
+                (Right, &[Name]),
+            ),
+        ),
+        (
+            "herma-4360".to_string(),
+            sheet(
+                A4,
+                (4.5, 0.0),
+                (3, 8),
+                (70.0, 36.0),
+                (0.0, 0.0),
+                (Right, &[Name, Path, Id]),
+            ),
+        ),
+        (
+            "avery-5160".to_string(),
+            sheet(
+                LETTER,
+                (12.7, 4.76),
+                (3, 10),
+                (66.68, 25.4),
+                (3.18, 0.0),
+                (Right, &[Name, Path]),
+            ),
+        ),
+        (
+            "avery-5163".to_string(),
+            sheet(
+                LETTER,
+                (12.7, 3.97),
+                (2, 5),
+                (101.6, 50.8),
+                (4.76, 0.0),
+                (Right, &[Name, Path, Id, Quantity]),
+            ),
+        ),
+        // big enough to list what is in a box - see `manifest`
+        (
+            crate::manifest::TEMPLATE.to_string(),
+            LabelTemplate {
+                layout: LabelLayout {
+                    fields: vec![Name, Path, Contents],
+                    text_position: Right,
+                    qr_size: Some(0.35),
+                    ..LabelLayout::default()
+                },
+                ..sheet(
+                    A4,
+                    (13.1, 4.65),
+                    (2, 4),
+                    (99.1, 67.7),
+                    (2.5, 0.0),
+                    (Right, &[]),
+                )
+            },
+        ),
+        // thermal printers - one label per page
+        (
+            "zebra-2x1".to_string(),
+            thermal(LabelTemplate {
+                dpi: 203.2,
+                ..sheet(
+                    (50.8, 25.4),
+                    (0.0, 0.0),
+                    (1, 1),
+                    (50.8, 25.4),
+                    (0.0, 0.0),
+                    (Right, &[Name, Path, Id]),
+                )
+            }),
+        ),
+        (
+            "brother-62".to_string(),
+            thermal(sheet(
+                (62.0, 29.0),
+                (0.0, 2.0),
+                (1, 1),
+                (58.0, 29.0),
+                (0.0, 0.0),
+                (Right, &[Name, Path, Id]),
+            )),
+        ),
+    ])
+}
+
+/// Look up a template by name, falling back to the configured default.
+pub fn find<'a>(state: &'a AppState, name: Option<&str>) -> Option<&'a LabelTemplate> {
+    state
+        .templates
+        .get(name.unwrap_or(&state.labels.default_template))
+}
+
+/// Every template that can be asked for, by name.
+#[get("/template")]
+pub fn list(state: &State<Arc<AppState>>) -> Json<HashMap<String, LabelTemplate>> {
+    Json(state.templates.clone())
+}
@@ -1,11 +1,29 @@
+use crate::barcode::ItemBarcode;
+use crate::blob::{BlobStore, Collected};
+use crate::bom::PickList;
+use crate::config::Config;
 use crate::container::Container;
 use crate::item::Item;
-use crate::item_location::ItemLocation;
+use crate::item_location::{ItemLocation, Pick};
+use crate::kit::{KitAvailability, KitBuild, KitComponent};
+use crate::labels::{PendingLabel, PendingReason};
+use crate::layout::{LabelData, LabelField, LabelLayout, LabelStyle, TextPosition};
+use crate::lookup::Lookup;
+use crate::manifest::StaleManifest;
+use crate::render::Renderer;
+use crate::scan::Scan;
+use crate::symbol::{EcLevel, Symbol, Symbology};
+use crate::template::LabelTemplate;
+use crate::text::Fonts;
+use crate::unit::Unit;
+use crate::vector::Segment;
 
 pub(crate) use super::rocket;
-use rocket::http::{ContentType, Status};
+use rocket::error::ErrorKind;
+use rocket::http::{Accept, ContentType, Header, Status};
 use rocket::local::blocking::{Client, LocalResponse};
 use rocket::Response;
+use std::collections::HashMap;
 use std::io::Cursor;
 
 #[test]
@@ -120,7 +138,7 @@ fn test_item() {
     assert_eq!(item.id, None);
     assert_eq!(item.name, "M3 Bolt, 20mm");
     assert_eq!(item.note, Some("Titanium".to_string()));
-    assert_eq!(item.photo, Some([0, 1, 2, 3, 4].into()));
+    assert_eq!(item.photo, None);
 
     let response = client.get("/item/2").dispatch();
 
@@ -130,7 +148,12 @@ fn test_item() {
     assert_eq!(item.id, None);
     assert_eq!(item.name, "M3 Bolt, 20mm");
     assert_eq!(item.note, Some("Titanium".to_string()));
-    assert_eq!(item.photo, Some([0, 1, 2, 3, 4].into()));
+    assert_eq!(item.photo, None);
+
+    // photos are fetched on their own
+    let response = client.get("/item/2/photo").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::Binary));
+    assert_eq!(response.into_bytes(), Some(vec![0, 1, 2, 3, 4]));
 }
 
 #[test]
@@ -185,3 +208,2398 @@ fn test_item_location() {
     let response = client.get("/itemloc/1").dispatch();
     assert_eq!(response.status(), Status::NotFound);
 }
+
+#[test]
+fn test_barcode_lookup() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "AA Battery" }"#)
+        .dispatch();
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Battery Drawer" }"#)
+        .dispatch();
+
+    // a UPC-A code, which is stored in its EAN-13 form
+    let response = client
+        .post("/item/barcode/1")
+        .header(ContentType::JSON)
+        .body(r#"{ "code": "036000291452" }"#)
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Created);
+    let barcode: ItemBarcode = response.into_json().expect("Valid response");
+    assert_eq!(barcode.code, "0036000291452");
+    assert_eq!(barcode.symbology, "upca");
+
+    let response = client
+        .post("/item/barcode/1")
+        .header(ContentType::JSON)
+        .body(r#"{ "code": "BAT-AA-48" }"#)
+        .dispatch();
+
+    let barcode: ItemBarcode = response.into_json().expect("Valid response");
+    assert_eq!(barcode.symbology, "code128");
+
+    // a code belongs to one item, however it was scanned
+    let response = client
+        .post("/item/barcode/1")
+        .header(ContentType::JSON)
+        .body(r#"{ "code": "0036000291452" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+
+    let response = client.get("/item/barcode/1").dispatch();
+    let barcodes: Vec<ItemBarcode> = response.into_json().expect("Valid response");
+    assert_eq!(barcodes.len(), 2);
+
+    // scanners may report the same code either way
+    for code in ["036000291452", "0036000291452"] {
+        let response = client.get(format!("/lookup?code={}", code)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        match response.into_json().expect("Valid response") {
+            Lookup::Item(item) => assert_eq!(item.name, "AA Battery"),
+            other => panic!("expected item, got {:?}", other),
+        }
+    }
+
+    // our own QR payloads
+    let response = client
+        .get("/lookup?code=http://foobar.com/container/1")
+        .dispatch();
+    match response.into_json().expect("Valid response") {
+        Lookup::Container(container) => assert_eq!(container.name, "Battery Drawer"),
+        other => panic!("expected container, got {:?}", other),
+    }
+
+    let response = client
+        .get("/lookup?code=https://example.org/item/1")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/lookup?code=4006381333931").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = client.delete(format!("/barcode/{}", barcode.id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/lookup?code=BAT-AA-48").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn test_kit() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+    for name in ["Blinky Kit", "Red LED", "220R Resistor"] {
+        client
+            .post("/item")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "{}" }}"#, name))
+            .dispatch();
+    }
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Parts Drawer" }"#)
+        .dispatch();
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Kit Shelf" }"#)
+        .dispatch();
+
+    for body in [
+        r#"{ "component_item_id": 2, "quantity": 2 }"#,
+        r#"{ "component_item_id": 3, "quantity": 2 }"#,
+    ] {
+        let response = client
+            .post("/kit/1/component")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    for body in [
+        r#"{ "component_item_id": 2, "quantity": 0 }"#,
+        r#"{ "component_item_id": 1, "quantity": 1 }"#,
+    ] {
+        let response = client
+            .post("/kit/1/component")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    // nor through the kits it goes into
+    let response = client
+        .post("/kit/2/component")
+        .header(ContentType::JSON)
+        .body(r#"{ "component_item_id": 1, "quantity": 1 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    for body in [
+        r#"{ "item_id": 2, "container_id": 1, "quantity": 3 }"#,
+        r#"{ "item_id": 2, "container_id": 1, "quantity": 2 }"#,
+        r#"{ "item_id": 3, "container_id": 1, "quantity": 4 }"#,
+    ] {
+        client
+            .post("/itemloc")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+
+    let response = client.get("/kit/1/availability?quantity=3").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let availability: KitAvailability = response.into_json().expect("Valid response");
+    assert_eq!(availability.buildable, 2);
+    assert_eq!(availability.components.len(), 2);
+    let resistors = &availability.components[1];
+    assert_eq!(resistors.name, "220R Resistor");
+    assert_eq!(resistors.required, 6);
+    assert_eq!(resistors.in_stock, 4);
+    assert_eq!(resistors.missing, 2);
+
+    let response = client.get("/kit/1/availability?quantity=0").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client
+        .get(format!("/kit/1/availability?quantity={}", i64::MAX))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client
+        .post("/kit/1/build")
+        .header(ContentType::JSON)
+        .body(r#"{ "container_id": 2, "quantity": -1 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client
+        .post("/kit/1/build")
+        .header(ContentType::JSON)
+        .body(format!(r#"{{ "container_id": 2, "quantity": {} }}"#, i64::MAX))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // not enough for three - nothing should be consumed
+    let response = client
+        .post("/kit/1/build")
+        .header(ContentType::JSON)
+        .body(r#"{ "container_id": 2, "quantity": 3 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+
+    let response = client
+        .post("/kit/1/build")
+        .header(ContentType::JSON)
+        .body(r#"{ "container_id": 2, "quantity": 2 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let build: KitBuild = response.into_json().expect("Valid response");
+    assert_eq!(build.picks.len(), 3); // the LEDs come out of two locations
+
+    let response = client.get("/itemloc/1").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(0));
+
+    let response = client.get("/itemloc/2").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(1));
+
+    // the assembled kits end up on the shelf
+    let response = client.get("/itemloc/4").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.item_id, 1);
+    assert_eq!(itemloc.container_id, 2);
+    assert_eq!(itemloc.quantity, Some(2));
+
+    let response = client.get("/kit/1/availability").dispatch();
+    let availability: KitAvailability = response.into_json().expect("Valid response");
+    assert_eq!(availability.buildable, 0);
+
+    // expired lots are not stock that kits can be built from
+    for body in [
+        r#"{ "item_id": 2, "container_id": 1, "quantity": 4 }"#,
+        r#"{ "item_id": 3, "container_id": 1, "quantity": 10, "lot": "R1", "expires_on": "2000-01-01" }"#,
+    ] {
+        client
+            .post("/itemloc")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+
+    let response = client.get("/kit/1/availability").dispatch();
+    let availability: KitAvailability = response.into_json().expect("Valid response");
+    assert_eq!(availability.buildable, 0);
+    assert_eq!(availability.components[1].in_stock, 0);
+    assert_eq!(availability.components[1].missing, 2);
+
+    let response = client
+        .post("/kit/1/build")
+        .header(ContentType::JSON)
+        .body(r#"{ "container_id": 2 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+    let availability: KitAvailability = response.into_json().expect("Valid response");
+    assert_eq!(availability.components[1].missing, 2);
+
+    let response = client.delete("/kit/component/2").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/kit/1/component").dispatch();
+    let components: Vec<KitComponent> = response.into_json().expect("Valid response");
+    assert_eq!(components.len(), 1);
+}
+
+#[test]
+fn test_kicad_bom() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Electronics Cabinet" }"#)
+        .dispatch();
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "parent_container_id": 1, "name": "Drawer 3" }"#)
+        .dispatch();
+
+    for body in [
+        r#"{ "name": "10k 0603", "value": "10k", "footprint": "R_0603_1608Metric" }"#,
+        r#"{ "name": "ATmega328P", "mpn": "ATMEGA328P-AU" }"#,
+        r#"{ "name": "100nF MLCC", "value": "100nF" }"#,
+    ] {
+        client
+            .post("/item")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+
+    for body in [
+        r#"{ "item_id": 1, "container_id": 2, "quantity": 5 }"#,
+        r#"{ "item_id": 2, "container_id": 2, "quantity": 1 }"#,
+        r#"{ "item_id": 3, "container_id": 1, "quantity": 10 }"#,
+    ] {
+        client
+            .post("/itemloc")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+
+    let bom = r#""Source:","/home/user/blinky/blinky.kicad_sch"
+"Component Count:","9"
+
+"Ref","Qty","Value","Footprint","MPN","DNP"
+"R1, R2","2","10k","Resistor_SMD:R_0603_1608Metric","",""
+"U1","1","ATmega328P","Package_QFP:TQFP-32_7x7mm_P0.8mm","ATMEGA328P-AU",""
+"C1 C2 C3","3","100nF","Capacitor_SMD:C_0402_1005Metric","",""
+"C4","1","100nF","Capacitor_SMD:C_0402_1005Metric","","DNP"
+"J1","1","Conn_01x02","Connector:Barrel_Jack","",""
+"#;
+
+    let response = client.post("/bom/kicad?boards=2").body(bom).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let pick_list: PickList = response.into_json().expect("Valid response");
+    assert!(!pick_list.picked);
+    assert_eq!(pick_list.lines.len(), 4);
+
+    let resistors = &pick_list.lines[0];
+    assert_eq!(resistors.line.references, ["R1", "R2"]);
+    assert_eq!(resistors.matched_by.as_deref(), Some("value_footprint"));
+    assert_eq!(resistors.required, 4);
+    assert_eq!(resistors.shortage, 0);
+    assert_eq!(
+        resistors.picks[0].container_path,
+        ["Electronics Cabinet", "Drawer 3"]
+    );
+
+    let mcu = &pick_list.lines[1];
+    assert_eq!(mcu.matched_by.as_deref(), Some("mpn"));
+    assert_eq!(mcu.shortage, 1);
+
+    let capacitors = &pick_list.lines[2];
+    assert_eq!(capacitors.matched_by.as_deref(), Some("value"));
+    assert_eq!(capacitors.required, 6);
+
+    let connector = &pick_list.lines[3];
+    assert_eq!(connector.item_id, None);
+    assert_eq!(connector.shortage, 2);
+
+    // previewing leaves stock alone
+    let response = client.get("/itemloc/1").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(5));
+
+    let response = client.post("/bom/kicad/pick?boards=2").body(bom).dispatch();
+    let pick_list: PickList = response.into_json().expect("Valid response");
+    assert!(pick_list.picked);
+
+    let response = client.get("/itemloc/1").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(1));
+
+    let response = client.get("/itemloc/2").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(0));
+
+    let response = client.post("/bom/kicad").body("not,a,bom").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client.post("/bom/kicad?boards=0").body(bom).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client
+        .post(format!("/bom/kicad/pick?boards={}", i64::MAX))
+        .body(bom)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    for qty in ["0", "-3"] {
+        let csv = format!("\"Ref\",\"Qty\",\"Value\"\n\"R1\",\"{}\",\"10k\"\n", qty);
+        let response = client.post("/bom/kicad").body(csv).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    // a BOM over the limit is refused rather than picked for in part
+    let row = "\"R9\",\"1\",\"10k\",\"\",\"\",\"\"\n";
+    let oversized = format!("{}{}", bom, row.repeat(50_000));
+    let response = client.post("/bom/kicad/pick").body(oversized).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client.get("/itemloc/3").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(4));
+}
+
+#[test]
+fn test_unit() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Rigol DS1054Z" }"#)
+        .dispatch();
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Instrument Shelf" }"#)
+        .dispatch();
+
+    let response = client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "serial_number": "DS1ZA1234", "purchase_date": "2021-03-14", "condition": "good" }"#)
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Created);
+    let unit: Unit = response.into_json().expect("Valid response");
+    assert_eq!(unit.id, None);
+    assert_eq!(unit.serial_number, "DS1ZA1234");
+
+    let response = client.get("/unit/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let unit: Unit = response.into_json().expect("Valid response");
+    assert_eq!(unit.item_id, 1);
+    assert_eq!(unit.container_id, 1);
+    assert_eq!(unit.purchase_date, Some("2021-03-14".to_string()));
+    assert_eq!(unit.condition, Some("good".to_string()));
+    assert_eq!(unit.note, None);
+
+    // serial numbers are unique per item type
+    let response = client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "serial_number": "DS1ZA1234" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "serial_number": "DS1ZA9999", "purchase_date": "last tuesday" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client.get("/unit?item_id=1").dispatch();
+    let ids: Vec<i64> = response.into_json().expect("Valid response");
+    assert_eq!(ids, [1]);
+
+    let response = client.get("/unit/qr/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+
+    for code in ["DS1ZA1234", "http://foobar.com/unit/1"] {
+        let response = client.get(format!("/lookup?code={}", code)).dispatch();
+        match response.into_json().expect("Valid response") {
+            Lookup::Unit(unit) => assert_eq!(unit.serial_number, "DS1ZA1234"),
+            other => panic!("expected unit, got {:?}", other),
+        }
+    }
+
+    let response = client.delete("/unit/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/unit/1").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // units go with the item or container they belong to
+    client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "serial_number": "DS1ZA5678" }"#)
+        .dispatch();
+    let response = client.delete("/item/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/unit/2").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Rigol DS1054Z" }"#)
+        .dispatch();
+    client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 2, "container_id": 1, "serial_number": "DS1ZA5678" }"#)
+        .dispatch();
+    let response = client.delete("/container/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/unit/3").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn test_lot_expiry() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Solder Paste, Sn63Pb37" }"#)
+        .dispatch();
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Fridge" }"#)
+        .dispatch();
+
+    for body in [
+        r#"{ "item_id": 1, "container_id": 1, "quantity": 2 }"#,
+        r#"{ "item_id": 1, "container_id": 1, "quantity": 2, "lot": "L2999", "expires_on": "2999-12-31" }"#,
+        r#"{ "item_id": 1, "container_id": 1, "quantity": 1, "lot": "L2000B", "expires_on": "2000-06-01" }"#,
+        r#"{ "item_id": 1, "container_id": 1, "quantity": 1, "lot": "L2000A", "expires_on": "2000-01-01" }"#,
+    ] {
+        let response = client
+            .post("/itemloc")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    let response = client
+        .post("/itemloc")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "expires_on": "soon" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client.get("/itemloc/2").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.lot, Some("L2999".to_string()));
+    assert_eq!(itemloc.expires_on, Some("2999-12-31".to_string()));
+
+    let response = client.get("/itemloc/expiring?days=30").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let expiring: Vec<ItemLocation> = response.into_json().expect("Valid response");
+    let lots: Vec<_> = expiring.iter().map(|l| l.lot.as_deref().unwrap()).collect();
+    assert_eq!(lots, ["L2000A", "L2000B"]);
+
+    let response = client.get("/itemloc/expiring?days=-5").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // first expired, first out - expired lots are never picked, and undated
+    // stock goes last
+    let response = client
+        .post("/itemloc/consume")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "quantity": 3 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let picks: Vec<Pick> = response.into_json().expect("Valid response");
+    let picked: Vec<_> = picks
+        .iter()
+        .map(|p| (p.item_location_id, p.quantity))
+        .collect();
+    assert_eq!(picked, [(2, 2), (1, 1)]);
+
+    let response = client.get("/itemloc/expiring?days=30").dispatch();
+    let expiring: Vec<ItemLocation> = response.into_json().expect("Valid response");
+    let lots: Vec<_> = expiring.iter().map(|l| l.lot.as_deref().unwrap()).collect();
+    assert_eq!(lots, ["L2000A", "L2000B"]);
+
+    let response = client
+        .post("/itemloc/consume")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "quantity": 2 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+
+    let response = client.get("/itemloc/1").dispatch();
+    let itemloc: ItemLocation = response.into_json().expect("Valid response");
+    assert_eq!(itemloc.quantity, Some(1));
+}
+
+#[test]
+fn test_qr_pdf_pagination() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+    let page_count = |client: &Client| {
+        let response = client.get("/container/qr").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::PDF));
+        let pdf = lopdf::Document::load_mem(&response.into_bytes().unwrap()).expect("Valid PDF");
+        pdf.get_pages().len()
+    };
+
+    // a full sheet holds 9 rows of 8 labels
+    for n in 1..=72 {
+        client
+            .post("/container")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "Bin {}" }}"#, n))
+            .dispatch();
+    }
+    assert_eq!(page_count(&client), 1);
+
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Bin 73" }"#)
+        .dispatch();
+    assert_eq!(page_count(&client), 2);
+}
+
+#[test]
+fn test_label_templates() {
+    let narrow = LabelTemplate {
+        page_width: 100.0,
+        page_height: 100.0,
+        margin_top: 0.0,
+        margin_left: 0.0,
+        columns: 1,
+        rows: 3,
+        label_width: 100.0,
+        label_height: 30.0,
+        column_gap: 0.0,
+        row_gap: 5.0,
+        dpi: 300.0,
+        layout: LabelLayout::default(),
+    };
+    let figment = rocket::Config::figment().merge(("label_templates.narrow", &narrow));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+
+    let response = client.get("/template").dispatch();
+    let templates: HashMap<String, LabelTemplate> = response.into_json().expect("Valid response");
+    assert_eq!(templates["narrow"], narrow);
+    assert!(templates.contains_key("yvonne-a4"));
+    assert!(templates.contains_key("avery-5160"));
+
+    for n in 1..=22 {
+        client
+            .post("/container")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "Bin {}" }}"#, n))
+            .dispatch();
+    }
+
+    let pages = |uri: &str| {
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let pdf = lopdf::Document::load_mem(&response.into_bytes().unwrap()).expect("Valid PDF");
+        let media_boxes: Vec<Vec<f32>> = pdf
+            .page_iter()
+            .map(|page| {
+                let page = pdf.get_dictionary(page).unwrap();
+                let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+                media_box
+                    .iter()
+                    .map(|n| n.as_float().unwrap().round())
+                    .collect()
+            })
+            .collect();
+        media_boxes
+    };
+
+    // 21 labels to a sheet
+    let a4 = pages("/container/qr?template=avery-l7160");
+    assert_eq!(a4, [[0.0, 0.0, 595.0, 842.0], [0.0, 0.0, 595.0, 842.0]]);
+
+    // 30 labels to a US letter sheet
+    let letter = pages("/container/qr?template=avery-5160");
+    assert_eq!(letter, [[0.0, 0.0, 612.0, 792.0]]);
+
+    assert_eq!(pages("/container/qr?template=narrow").len(), 8);
+
+    let response = client
+        .get("/container/qr?template=no-such-sheet")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // labels that do not fit on their page are rejected at startup
+    let too_wide = LabelTemplate {
+        columns: 2,
+        ..narrow
+    };
+    let figment = rocket::Config::figment().merge(("label_templates.too-wide", &too_wide));
+    let error =
+        Client::tracked(rocket().configure(figment)).expect_err("invalid template rejected");
+    assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+}
+
+#[test]
+fn test_label_layout() {
+    let fonts = &Fonts::builtin();
+    let label_layout = LabelLayout {
+        fields: vec![LabelField::Name, LabelField::Path, LabelField::Quantity],
+        text_position: TextPosition::Right,
+        ..LabelLayout::default()
+    };
+
+    let mut short = LabelData::new(1, "item", "M3".to_string());
+    let layout = crate::layout::layout(fonts, &short, &label_layout, 750, 450);
+    assert_eq!(
+        (layout.code_x, layout.code_width, layout.code_height),
+        (0, 375, 375)
+    );
+    // fields the item does not have are left out
+    assert_eq!(layout.lines.len(), 1);
+    let short_scale = layout.lines[0].scale;
+
+    short.name = "M3 x 20mm socket head cap screw, A4 stainless, DIN 912".to_string();
+    short.path = Some("1000 Washington Street / Toolchest / Drawer 2".to_string());
+    short.quantity = Some(150);
+    let layout = crate::layout::layout(fonts, &short, &label_layout, 750, 450);
+    assert!(layout.lines.len() > 3);
+    assert!(layout.lines[0].scale < short_scale);
+    assert_eq!(layout.lines.last().unwrap().text, "Qty 150");
+    for line in &layout.lines {
+        let right = line.x + crate::layout::text_width(fonts, line.scale, &line.text);
+        assert!(line.x >= 375.0 && right <= 750.0, "{:?} overflows", line);
+        assert!(
+            line.y >= 0.0 && line.y + line.scale <= 450.0,
+            "{:?} overflows",
+            line
+        );
+    }
+
+    // far too much text for a tiny label gets cut short rather than overflowing
+    short.name = "word ".repeat(200);
+    let layout = crate::layout::layout(fonts, &short, &LabelLayout::default(), 100, 125);
+    assert!(layout.lines.last().unwrap().text.ends_with('…'));
+    let bottom = layout.lines.last().map(|l| l.y + l.scale).unwrap();
+    assert!(bottom <= 125.0);
+
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "M3 x 20mm socket head cap screw" }"#)
+        .dispatch();
+
+    let response = client.get("/item/qr/1?template=avery-l7160").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    let png = image::load_from_memory(&response.into_bytes().unwrap()).expect("Valid PNG");
+    assert_eq!((png.width(), png.height()), (750, 450));
+
+    let response = client.get("/item/qr/1").dispatch();
+    let png = image::load_from_memory(&response.into_bytes().unwrap()).expect("Valid PNG");
+    assert_eq!((png.width(), png.height()), (300, 375));
+}
+
+#[test]
+fn test_selective_labels() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    for body in [
+        r#"{ "name": "1000 Washington Street" }"#,
+        r#"{ "parent_container_id": 1, "name": "Toolchest" }"#,
+        r#"{ "parent_container_id": 2, "name": "Top Drawer" }"#,
+        r#"{ "name": "Shed" }"#,
+    ] {
+        client
+            .post("/container")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+    for body in [r#"{ "name": "M3 screw" }"#, r#"{ "name": "M4 screw" }"#] {
+        client
+            .post("/item")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+    for body in [
+        r#"{ "item_id": 1, "container_id": 3, "quantity": 10 }"#,
+        r#"{ "item_id": 2, "container_id": 4, "quantity": 10 }"#,
+    ] {
+        client
+            .post("/itemloc")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+
+    // (pages, labels printed)
+    let print = |body: &str| {
+        let response = client
+            .post("/labels")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok, "{}", body);
+        assert_eq!(response.content_type(), Some(ContentType::PDF));
+        let pdf = lopdf::Document::load_mem(&response.into_bytes().unwrap()).expect("Valid PDF");
+        // each label is drawn in its own coordinate system
+        let labels = pdf
+            .get_pages()
+            .values()
+            .flat_map(|&page| pdf.get_and_decode_page_content(page).unwrap().operations)
+            .filter(|op| op.operator == "cm")
+            .count();
+        (pdf.get_pages().len(), labels)
+    };
+
+    let ids = r#"[{ "kind": "container", "id": 4 }, { "kind": "item", "id": 1 }]"#;
+    assert_eq!(print(&format!(r#"{{ "ids": {} }}"#, ids)), (1, 2));
+    // the toolchest, its drawer and the screws in it
+    assert_eq!(print(r#"{ "subtree": 2 }"#), (1, 3));
+    assert_eq!(
+        print(r#"{ "subtree": 1, "filter": { "kinds": ["container"] } }"#),
+        (1, 3)
+    );
+    assert_eq!(print(r#"{ "filter": { "name": "SCREW" } }"#), (1, 2));
+    // the item selected by the filter is only printed once
+    assert_eq!(
+        print(&format!(
+            r#"{{ "ids": {}, "filter": {{ "name": "m3" }} }}"#,
+            ids
+        )),
+        (1, 2)
+    );
+
+    // a sheet holds 72 labels - starting at the last slot pushes the second
+    // label onto a new page
+    assert_eq!(
+        print(&format!(r#"{{ "ids": {}, "offset": 71 }}"#, ids)),
+        (2, 2)
+    );
+    assert_eq!(
+        print(&format!(r#"{{ "ids": {}, "offset": 70 }}"#, ids)),
+        (1, 2)
+    );
+
+    for body in [
+        r#"{ "ids": [{ "kind": "item", "id": 3 }] }"#,
+        r#"{ "filter": { "name": "hammer" } }"#,
+        r#"{ "subtree": 1, "offset": 72 }"#,
+        r#"{ "subtree": 1, "template": "avery-0000" }"#,
+    ] {
+        let response = client
+            .post("/labels")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest, "{}", body);
+    }
+}
+
+#[test]
+fn test_vector_labels() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+
+    let response = client
+        .get("/container/svg/1?template=avery-l7160")
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::SVG));
+    let svg = response.into_string().unwrap();
+    assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="63.5mm" height="38.1mm" viewBox="0 0 750 450">"#));
+    assert_eq!(svg.matches("<path ").count(), 4);
+    assert_eq!(
+        client.get("/container/svg/2").dispatch().status(),
+        Status::NotFound
+    );
+
+    // the vector QR code has its dark modules where the raster one does
+    let state = crate::AppState {
+        root_url: String::from("http://localhost:8000"),
+        templates: crate::template::builtin(),
+        labels: Default::default(),
+        fonts: Fonts::builtin(),
+        renderer: Renderer::new(&Default::default()),
+        blobs: BlobStore::open(&Default::default()).unwrap(),
+    };
+    let template = &state.templates[crate::template::DEFAULT_TEMPLATE];
+    let container: Container = client.get("/container/1").dispatch().into_json().unwrap();
+    let mut label = LabelData::new(1, "container", "Toolchest".to_string());
+    label.code = container.code;
+    let (width, height) = template.label_pixels();
+    let layout = crate::layout::layout(&state.fonts, &label, &template.layout, width, height);
+    let paths = crate::vector::label_paths(&state, &label, &template.layout, &layout).unwrap();
+
+    let response = client.get("/container/qr/1").dispatch();
+    let png = image::load_from_memory(&response.into_bytes().unwrap())
+        .expect("Valid PNG")
+        .to_luma8();
+    let modules = &paths[2];
+    assert_eq!(modules.luma, 0);
+    let mut corners = modules.segments.iter().filter_map(|segment| match segment {
+        Segment::MoveTo(x, y) => Some((x, y)),
+        _ => None,
+    });
+    let (x, y) = corners.next().unwrap();
+    let module = layout.code_width as f32 / 27.0;
+    assert!((x - module).abs() < 0.01 && (y - module).abs() < 0.01);
+    for (x, y) in corners {
+        let center = png.get_pixel((x + module / 2.0) as u32, (y + module / 2.0) as u32);
+        assert_eq!(center.0, [0], "module at {}, {}", x, y);
+    }
+
+    // the text stays on the label
+    let text = &paths[3];
+    assert_eq!(text.luma, 255);
+    assert!(!text.segments.is_empty());
+    for segment in &text.segments {
+        if let Segment::MoveTo(x, y) | Segment::LineTo(x, y) = segment {
+            assert!(
+                (0.0..width as f32).contains(x)
+                    && (layout.code_height as f32..height as f32).contains(y),
+                "{:?} in {:?}",
+                segment,
+                layout.lines
+            );
+        }
+    }
+
+    // sheets are drawn as vectors, not embedded bitmaps
+    let response = client.get("/container/qr").dispatch();
+    let pdf = lopdf::Document::load_mem(&response.into_bytes().unwrap()).expect("Valid PDF");
+    let images = pdf
+        .objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .filter(|stream| {
+            stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image")
+        })
+        .count();
+    assert_eq!(images, 0);
+    let page = pdf.get_pages()[&1];
+    let operators: Vec<String> = pdf
+        .get_and_decode_page_content(page)
+        .unwrap()
+        .operations
+        .into_iter()
+        .map(|op| op.operator)
+        .collect();
+    assert_eq!(operators.iter().filter(|op| *op == "f").count(), 4);
+    assert!(operators.contains(&"c".to_string()));
+}
+
+#[test]
+fn test_thermal_labels() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    for name in ["Toolchest", "Shed"] {
+        client
+            .post("/container")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "{}" }}"#, name))
+            .dispatch();
+    }
+    let dark = |template: &str| {
+        let response = client
+            .get(format!("/container/qr/1?template={}", template))
+            .dispatch();
+        let png = image::load_from_memory(&response.into_bytes().unwrap()).expect("Valid PNG");
+        png.to_luma8()
+    };
+
+    // 2 by 1 inches at 8 dots per millimetre
+    let response = client
+        .get("/container/thermal/1?format=zpl&template=zebra-2x1")
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::Plain));
+    let zpl = response.into_string().unwrap();
+    let header = "^XA\n^PW406\n^LL203\n^FO0,0^GFA,10353,10353,51,";
+    assert!(zpl.starts_with(header));
+    assert!(zpl.ends_with("^FS\n^XZ\n"));
+    let hex = &zpl[header.len()..zpl.len() - "^FS\n^XZ\n".len()];
+    assert_eq!(hex.len(), 2 * 10353);
+    let image = dark("zebra-2x1");
+    for (y, row) in hex.as_bytes().chunks(2 * 51).enumerate() {
+        let row = std::str::from_utf8(row).unwrap();
+        for x in 0..406 {
+            let byte = u8::from_str_radix(&row[x / 8 * 2..x / 8 * 2 + 2], 16).unwrap();
+            let printed = byte & (0x80 >> (x % 8)) != 0;
+            let pixel = image.get_pixel(x as u32, y as u32).0[0];
+            assert_eq!(printed, pixel < 128, "dot {}, {}", x, y);
+        }
+    }
+
+    // 58mm wide labels on 62mm tape at 300 dpi are 685 by 343 dots
+    let response = client
+        .get("/container/thermal/1?format=brother_ql&template=brother-62")
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::Binary));
+    let job = response.into_bytes().unwrap();
+    assert!(job[..200].iter().all(|&b| b == 0));
+    let mut expected = vec![0x1b, b'@', 0x1b, b'i', b'a', 0x01];
+    expected.extend([0x1b, b'i', b'z', 0x86, 0x0a, 62, 0x00]);
+    expected.extend(343u32.to_le_bytes());
+    expected.extend([0x00, 0x00]);
+    assert_eq!(&job[200..200 + expected.len()], &expected[..]);
+    let rows = &job[job.len() - 1 - 343 * 93..job.len() - 1];
+    assert_eq!(job.last(), Some(&0x1a));
+    assert_eq!(&job[job.len() - 1 - 343 * 93 - 2..][..2], &[b'M', 0x00]);
+    let image = dark("brother-62");
+    assert_eq!(image.dimensions(), (685, 343));
+    for (y, row) in rows.chunks(93).enumerate() {
+        assert_eq!(&row[..3], &[b'g', 0x00, 90]);
+        // mirrored, and centred in the 696 dots printable from dot 12
+        for x in 0..685 {
+            let dot = 17 + 684 - x;
+            let printed = row[3 + dot / 8] & (0x80 >> (dot % 8)) != 0;
+            let pixel = image.get_pixel(x as u32, y as u32).0[0];
+            assert_eq!(printed, pixel < 128, "dot {}, {}", x, y);
+        }
+    }
+
+    // A4 is not a tape width
+    let response = client
+        .get("/container/thermal/1?format=brother_ql")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = client.get("/container/thermal/1?format=dymo").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = client
+        .post("/labels")
+        .header(ContentType::JSON)
+        .body(r#"{ "filter": {}, "template": "zebra-2x1", "format": "zpl" }"#)
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::Plain));
+    assert_eq!(response.into_string().unwrap().matches("^XA").count(), 2);
+
+    let response = client
+        .post("/labels")
+        .header(ContentType::JSON)
+        .body(r#"{ "filter": {}, "template": "brother-62", "format": "brother_ql" }"#)
+        .dispatch();
+    let job = response.into_bytes().unwrap();
+    // two pages, the first fed with 0x0c and the last with 0x1a
+    let page_length = 13 + 4 + 4 + 4 + 5 + 2 + 343 * 93 + 1;
+    assert_eq!(job[200 + 6 + page_length - 1], 0x0c);
+    assert_eq!(job.last(), Some(&0x1a));
+    assert_eq!(job.len(), 200 + 6 + 2 * page_length);
+}
+
+#[test]
+fn test_symbologies() {
+    let url = "http://foobar.com/container/1";
+    let qr = Symbol::encode(url, Symbology::Qr, EcLevel::Low).unwrap();
+    assert_eq!((qr.modules.len(), qr.modules[0].len()), (25, 25));
+    let robust = Symbol::encode(url, Symbology::Qr, EcLevel::High).unwrap();
+    assert!(robust.modules.len() > 25);
+
+    // Micro QR codes only hold short payloads
+    assert!(Symbol::encode(url, Symbology::MicroQr, EcLevel::Low).is_err());
+    let micro = Symbol::encode("C-7K2P", Symbology::MicroQr, EcLevel::Low).unwrap();
+    assert!(micro.modules.len() <= 17);
+    assert!(Symbology::MicroQr.validate(EcLevel::High).is_err());
+
+    let data_matrix = Symbol::encode(url, Symbology::DataMatrix, EcLevel::Low).unwrap();
+    assert!(data_matrix.modules.len() < 25);
+    // the solid L finder pattern on the left and bottom edges
+    assert!(data_matrix.modules.iter().all(|row| row[0]));
+    assert!(data_matrix.modules.last().unwrap().iter().all(|&m| m));
+
+    let code128 = Symbol::encode(url, Symbology::Code128, EcLevel::Low).unwrap();
+    assert_eq!(code128.modules.len(), 1);
+    let bars: String = code128.modules[0]
+        .iter()
+        .map(|&m| if m { '1' } else { '0' })
+        .collect();
+    assert!(bars.starts_with("11010010000")); // start B
+    assert!(bars.ends_with("1100011101011")); // stop
+                                              // a character and its checksum are 11 modules each
+    assert_eq!(bars.len(), 11 * (url.len() + 2) + 13);
+
+    // bars fill the height of a linear code, with whole-pixel modules
+    let image = code128.to_image(1000, 40).unwrap();
+    let placement = code128.place(1000.0, 40.0, true).unwrap();
+    assert_eq!(
+        (placement.module_width, placement.module_height),
+        (2.0, 40.0)
+    );
+    for (column, &dark) in code128.modules[0].iter().enumerate() {
+        let x = placement.x as u32 + 2 * column as u32;
+        assert_eq!(image.get_pixel(x, 0).0[0] == 0, dark);
+        assert_eq!(image.get_pixel(x + 1, 39).0[0] == 0, dark);
+    }
+    assert!(code128.to_image(300, 40).is_err());
+
+    let tiny = LabelTemplate {
+        page_width: 210.0,
+        page_height: 297.0,
+        margin_top: 0.0,
+        margin_left: 0.0,
+        columns: 20,
+        rows: 29,
+        label_width: 10.0,
+        label_height: 10.0,
+        column_gap: 0.0,
+        row_gap: 0.0,
+        dpi: 300.0,
+        layout: LabelLayout {
+            fields: Vec::new(),
+            symbology: Symbology::DataMatrix,
+            ..LabelLayout::default()
+        },
+    };
+    let figment = rocket::Config::figment().merge(("label_templates.tiny", &tiny));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    let container: Container = client.get("/container/1").dispatch().into_json().unwrap();
+    let payload = format!("http://localhost:8000/q/{}", container.code.unwrap());
+    let data_matrix = Symbol::encode(&payload, Symbology::DataMatrix, EcLevel::Low).unwrap();
+
+    let response = client.get("/container/qr/1?template=tiny").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    let png = image::load_from_memory(&response.into_bytes().unwrap()).expect("Valid PNG");
+    let (width, _) = tiny.label_pixels();
+    let module = width / (data_matrix.modules.len() as u32 + 2);
+    let margin = (width - module * data_matrix.modules.len() as u32) / 2;
+    let png = png.to_luma8();
+    for (y, row) in data_matrix.modules.iter().enumerate() {
+        for (x, &dark) in row.iter().enumerate() {
+            let pixel = png.get_pixel(margin + module * x as u32, margin + module * y as u32);
+            assert_eq!(pixel.0[0] == 0, dark, "module {}, {}", x, y);
+        }
+    }
+
+    let print = |body: &str| {
+        client
+            .post("/labels")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .status()
+    };
+    assert_eq!(
+        print(r#"{ "filter": {}, "symbology": "code128", "template": "avery-l7163" }"#),
+        Status::Ok
+    );
+    assert_eq!(print(r#"{ "filter": {}, "ec_level": "high" }"#), Status::Ok);
+    assert_eq!(
+        print(r#"{ "filter": {}, "symbology": "micro_qr" }"#),
+        Status::BadRequest
+    );
+    assert_eq!(
+        print(r#"{ "filter": {}, "symbology": "micro_qr", "ec_level": "high" }"#),
+        Status::BadRequest
+    );
+    // a 25mm wide label has no room for a URL as Code128
+    assert_eq!(
+        print(r#"{ "filter": {}, "symbology": "code128" }"#),
+        Status::BadRequest
+    );
+}
+
+#[test]
+fn test_short_codes() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "M3 screw" }"#)
+        .dispatch();
+    client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "serial_number": "DS1ZA1234" }"#)
+        .dispatch();
+
+    let container: Container = client.get("/container/1").dispatch().into_json().unwrap();
+    let item: Item = client.get("/item/1").dispatch().into_json().unwrap();
+    let unit: Unit = client.get("/unit/1").dispatch().into_json().unwrap();
+    let codes = [
+        container.code.unwrap(),
+        item.code.unwrap(),
+        unit.code.unwrap(),
+    ];
+    for (code, prefix) in codes.iter().zip(["C-", "I-", "U-"]) {
+        assert!(code.starts_with(prefix), "{}", code);
+        assert_eq!(code.len(), 6);
+        assert!(code[2..]
+            .chars()
+            .all(|c| c.is_ascii_digit() || (c.is_ascii_uppercase() && !"ILOU".contains(c))));
+    }
+
+    // codes do not change once given
+    client.get("/container/qr/1").dispatch();
+    let again: Container = client.get("/container/1").dispatch().into_json().unwrap();
+    assert_eq!(again.code.as_ref(), Some(&codes[0]));
+
+    // the resolver sends scanners to the entity on whatever host they used
+    for (code, location) in codes.iter().zip(["/container/1", "/item/1", "/unit/1"]) {
+        let response = client.get(format!("/q/{}", code)).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("Location"), Some(location));
+    }
+    // lowercase, and letters that look like digits, are read as the code
+    let sloppy = codes[0].to_lowercase().replace('0', "o").replace('1', "l");
+    let response = client.get(format!("/q/{}", sloppy)).dispatch();
+    assert_eq!(response.headers().get_one("Location"), Some("/container/1"));
+    assert_eq!(
+        client.get("/q/C-UUUU").dispatch().status(),
+        Status::NotFound
+    );
+
+    // labels printed under an old domain still resolve
+    for scanned in [
+        codes[1].clone(),
+        format!("https://old.example.org/q/{}", codes[1]),
+        format!("http://foobar.com/q/{}", codes[1].to_lowercase()),
+    ] {
+        let response = client
+            .get(format!(
+                "/lookup?code={}",
+                scanned.replace(':', "%3A").replace('/', "%2F")
+            ))
+            .dispatch();
+        match response.into_json::<Lookup>() {
+            Some(Lookup::Item(item)) => assert_eq!(item.name, "M3 screw"),
+            other => panic!("{} looked up {:?}", scanned, other),
+        }
+    }
+
+    // deleting an entity frees its code
+    client.delete("/unit/1").dispatch();
+    let response = client.get(format!("/q/{}", codes[2])).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn test_config() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    let config: Config = client.get("/config").dispatch().into_json().unwrap();
+    assert_eq!(config.root_url, "http://localhost:8000");
+    assert_eq!(config.labels.default_template, "yvonne-a4");
+    assert_eq!(config.labels.qr_size, None);
+
+    let figment = rocket::Config::figment()
+        .merge(("root_url", "https://inventory.example.com/"))
+        .merge(("labels.default_template", "avery-l7160"))
+        .merge(("labels.qr_size", 0.5));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    let config: Config = client.get("/config").dispatch().into_json().unwrap();
+    assert_eq!(config.root_url, "https://inventory.example.com");
+    assert_eq!(config.labels.default_template, "avery-l7160");
+    assert_eq!(config.labels.qr_size, Some(0.5));
+
+    // templates without their own qr_size get the configured one
+    let templates: HashMap<String, LabelTemplate> =
+        client.get("/template").dispatch().into_json().unwrap();
+    assert_eq!(templates["avery-l7160"].layout.qr_size, Some(0.5));
+    assert_eq!(templates[crate::manifest::TEMPLATE].layout.qr_size, Some(0.35));
+
+    // labels come out on the configured default template
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    let response = client.get("/container/qr/1").dispatch();
+    let png = image::load_from_memory(&response.into_bytes().unwrap()).expect("Valid PNG");
+    assert_eq!((png.width(), png.height()), templates["avery-l7160"].label_pixels());
+    let response = client
+        .get("/lookup?code=https%3A%2F%2Finventory.example.com%2Fcontainer%2F1")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // bad settings stop the server from starting
+    let bad = [
+        ("root_url", "inventory.example.com"),
+        ("root_url", "ftp://inventory.example.com"),
+        ("labels.default_template", "no-such-sheet"),
+        ("labels.font", "/no/such/font.ttf"),
+    ];
+    for (key, value) in bad {
+        let figment = rocket::Config::figment().merge((key, value));
+        let error = Client::tracked(rocket().configure(figment)).expect_err("bad config rejected");
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+    }
+    let figment = rocket::Config::figment().merge(("labels.qr_size", 1.5));
+    let error = Client::tracked(rocket().configure(figment)).expect_err("bad qr_size rejected");
+    assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+}
+
+#[test]
+fn test_html_pages() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    for body in [
+        r#"{ "name": "1000 Washington Street" }"#,
+        r#"{ "parent_container_id": 1, "name": "Toolchest <Top>" }"#,
+    ] {
+        client
+            .post("/container")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Resistors & caps", "mpn": "RC0603" }"#)
+        .dispatch();
+    client
+        .post("/itemloc")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 2, "quantity": 5, "lot": "L7" }"#)
+        .dispatch();
+    client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 2, "serial_number": "SN1" }"#)
+        .dispatch();
+
+    // browsers get a page, with text escaped
+    let response = client.get("/container/2").header(Accept::HTML).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let html = response.into_string().unwrap();
+    assert!(html.contains("<h1>Toolchest &lt;Top&gt;</h1>"));
+    assert!(html.contains(r#"<nav><a href="/container/1">1000 Washington Street</a></nav>"#));
+    assert!(html.contains(r#"<a href="/item/1">Resistors &amp; caps (lot L7)</a>"#));
+    assert!(html.contains(r#"action="/itemloc/1/adjust""#));
+    assert!(html.contains(r#"<a href="/unit/1">Resistors &amp; caps S/N SN1</a>"#));
+
+    let html = client
+        .get("/item/1")
+        .header(Accept::HTML)
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(html.contains("<dt>In stock</dt><dd>5</dd>"));
+    assert!(html.contains("<dd>RC0603</dd>"));
+    let html = client
+        .get("/unit/1")
+        .header(Accept::HTML)
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(html.contains("<h1>Resistors &amp; caps S/N SN1</h1>"));
+    assert!(html.contains(r#"<a href="/container/2">Toolchest &lt;Top&gt;</a>"#));
+    assert_eq!(
+        client.get("/container/3").header(Accept::HTML).dispatch().status(),
+        Status::NotFound
+    );
+
+    // API clients still get JSON
+    for response in [
+        client.get("/container/2").dispatch(),
+        client.get("/container/2").header(Accept::JSON).dispatch(),
+        client.get("/container/2").header(Accept::Any).dispatch(),
+    ] {
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let container: Container = response.into_json().unwrap();
+        assert_eq!(container.name, "Toolchest <Top>");
+    }
+
+    // the quantity buttons go back to the page, and stop at none
+    let response = client
+        .post("/itemloc/1/adjust")
+        .header(ContentType::Form)
+        .body("delta=-1&back=/container/2")
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/container/2"));
+    let itemloc: ItemLocation = client.get("/itemloc/1").dispatch().into_json().unwrap();
+    assert_eq!(itemloc.quantity, Some(4));
+    let response = client
+        .post("/itemloc/1/adjust")
+        .header(ContentType::Form)
+        .body("delta=-10&back=//evil.example.com")
+        .dispatch();
+    assert_eq!(response.headers().get_one("Location"), Some("/"));
+    let itemloc: ItemLocation = client.get("/itemloc/1").dispatch().into_json().unwrap();
+    assert_eq!(itemloc.quantity, Some(0));
+    assert_eq!(
+        client
+            .post("/itemloc/2/adjust")
+            .header(ContentType::Form)
+            .body("delta=1&back=/")
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+}
+
+#[test]
+fn test_scan() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Resistor" }"#)
+        .dispatch();
+
+    // a photo of two of our labels and a code of someone else's
+    let mut photo = image::GrayImage::from_pixel(1100, 400, image::Luma([255]));
+    for (i, uri) in ["/container/qr/1", "/item/qr/1"].iter().enumerate() {
+        let response = client.get(*uri).dispatch();
+        let label = image::load_from_memory(&response.into_bytes().unwrap())
+            .expect("Valid PNG")
+            .to_luma8();
+        image::imageops::overlay(&mut photo, &label, 50 + 350 * i as i64, 50);
+    }
+    let other = Symbol::encode("hello", Symbology::Qr, EcLevel::Low)
+        .unwrap()
+        .to_image(250, 250)
+        .unwrap();
+    image::imageops::overlay(&mut photo, &other, 800, 75);
+    let mut png = Vec::new();
+    photo
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+
+    let response = client.post("/scan").body(png).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let scan: Scan = response.into_json().unwrap();
+    let mut names: Vec<String> = scan
+        .found
+        .iter()
+        .map(|found| match found {
+            Lookup::Container(container) => format!("container {}", container.name),
+            Lookup::Item(item) => format!("item {}", item.name),
+            Lookup::Unit(unit) => format!("unit {}", unit.serial_number),
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names, ["container Toolchest", "item Resistor"]);
+    assert_eq!(scan.unknown, ["hello"]);
+
+    assert_eq!(
+        client.post("/scan").body("not an image").dispatch().status(),
+        Status::BadRequest
+    );
+
+    let figment = rocket::Config::figment().merge(("limits.scan", "1 KiB"));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    let response = client.post("/scan").body(vec![0; 2048]).dispatch();
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+}
+
+#[test]
+fn test_manifest() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Storage box" }"#)
+        .dispatch();
+    for name in ["Resistor", "Capacitor", "Fuse"] {
+        client
+            .post("/item")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "{}" }}"#, name))
+            .dispatch();
+    }
+    for body in [
+        r#"{ "item_id": 1, "container_id": 1, "quantity": 5 }"#,
+        r#"{ "item_id": 1, "container_id": 1, "quantity": 3, "lot": "L2" }"#,
+        r#"{ "item_id": 2, "container_id": 1, "quantity": null }"#,
+        r#"{ "item_id": 3, "container_id": 1, "quantity": 0 }"#,
+    ] {
+        client
+            .post("/itemloc")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+
+    let stale = |client: &Client| -> Vec<StaleManifest> {
+        client
+            .get("/container/manifest/stale")
+            .dispatch()
+            .into_json()
+            .unwrap()
+    };
+    assert!(stale(&client).is_empty());
+
+    let response = client.post("/container/manifest/1").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PDF));
+    let pdf = lopdf::Document::load_mem(&response.into_bytes().unwrap()).unwrap();
+    assert_eq!(pdf.get_pages().len(), 1);
+    assert!(stale(&client).is_empty());
+
+    // the contents fit on the label, a line per item
+    let templates = crate::template::builtin();
+    let template = &templates[crate::manifest::TEMPLATE];
+    let mut label = LabelData::new(1, "container", "Storage box".to_string());
+    label.contents = vec!["Capacitor".to_string(), "8 × Resistor".to_string()];
+    let (width, height) = template.label_pixels();
+    let layout = crate::layout::layout(&Fonts::builtin(), &label, &template.layout, width, height);
+    let lines: Vec<&str> = layout.lines.iter().map(|line| line.text.as_str()).collect();
+    assert!(lines.ends_with(&["Capacitor", "8 × Resistor"]));
+    assert!(lines.iter().all(|line| !line.ends_with('…')));
+
+    // taking some out makes the label out of date until it is reprinted
+    client
+        .post("/itemloc/consume")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "quantity": 2 }"#)
+        .dispatch();
+    let stale_manifests = stale(&client);
+    assert_eq!(stale_manifests.len(), 1);
+    assert_eq!(stale_manifests[0].container_id, 1);
+    assert_eq!(stale_manifests[0].name, "Storage box");
+    assert_eq!(stale_manifests[0].printed, ["Capacitor", "8 × Resistor"]);
+    assert_eq!(stale_manifests[0].current, ["Capacitor", "6 × Resistor"]);
+
+    let response = client
+        .post("/container/manifest/1?template=avery-l7163")
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PDF));
+    assert!(stale(&client).is_empty());
+
+    assert_eq!(
+        client.post("/container/manifest/2").dispatch().status(),
+        Status::NotFound
+    );
+    assert_eq!(
+        client
+            .post("/container/manifest/1?template=nope")
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+}
+
+#[test]
+fn test_print_queue() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    for body in [
+        r#"{ "name": "Shelf" }"#,
+        r#"{ "parent_container_id": 1, "name": "Bin" }"#,
+    ] {
+        client
+            .post("/container")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+    }
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Resistor" }"#)
+        .dispatch();
+
+    let pending = |client: &Client| -> Vec<(String, i64, PendingReason)> {
+        let pending: Vec<PendingLabel> = client
+            .get("/labels/pending")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        pending
+            .into_iter()
+            .map(|p| (p.kind.route().to_string(), p.id, p.reason))
+            .collect()
+    };
+    let unprinted = PendingReason::Unprinted;
+    let outdated = PendingReason::Outdated;
+    assert_eq!(
+        pending(&client),
+        [
+            ("container".to_string(), 1, unprinted),
+            ("container".to_string(), 2, unprinted),
+            ("item".to_string(), 1, unprinted),
+        ]
+    );
+
+    // printing some labels takes them off the list
+    let response = client
+        .post("/labels")
+        .header(ContentType::JSON)
+        .body(r#"{ "ids": [{ "kind": "container", "id": 2 }], "template": "avery-l7160" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        pending(&client),
+        [
+            ("container".to_string(), 1, unprinted),
+            ("item".to_string(), 1, unprinted),
+        ]
+    );
+
+    // and printing all pending takes off the rest
+    let response = client.post("/labels/pending").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PDF));
+    let pdf = lopdf::Document::load_mem(&response.into_bytes().unwrap()).expect("Valid PDF");
+    let labels = pdf
+        .get_pages()
+        .values()
+        .flat_map(|&page| pdf.get_and_decode_page_content(page).unwrap().operations)
+        .filter(|op| op.operator == "cm")
+        .count();
+    assert_eq!(labels, 2);
+    assert!(pending(&client).is_empty());
+    assert_eq!(
+        client.post("/labels/pending").dispatch().status(),
+        Status::BadRequest
+    );
+
+    // renaming the shelf outdates its label, and the bin's, which shows
+    // where the bin is
+    client
+        .put("/container/1")
+        .header(ContentType::JSON)
+        .body(r#"{ "parent_container_id": null, "name": "Top shelf", "note": null, "photo": null }"#)
+        .dispatch();
+    let listed: Vec<PendingLabel> = client
+        .get("/labels/pending")
+        .dispatch()
+        .into_json()
+        .unwrap();
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed[0].name, "Top shelf");
+    assert_eq!(listed[0].reason, outdated);
+    assert!(listed[0].printed_at.is_some());
+    assert_eq!((listed[1].id, listed[1].reason), (2, outdated));
+
+    assert_eq!(
+        client
+            .post("/labels/pending?template=nope")
+            .dispatch()
+            .status(),
+        Status::BadRequest
+    );
+    let response = client
+        .post("/labels/pending?template=zebra-2x1&format=zpl")
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::Plain));
+    assert_eq!(response.into_string().unwrap().matches("^XA").count(), 2);
+    assert!(pending(&client).is_empty());
+}
+
+#[test]
+fn test_label_text() {
+    let fonts = Fonts::builtin();
+    // marks are shaped onto the letter before them rather than beside it
+    let width = fonts.width("e", 40.0);
+    assert!(width > 0.0);
+    assert!((fonts.width("e\u{301}", 40.0) - width).abs() < 0.01);
+    assert!((fonts.width("cafe\u{301}", 40.0) - fonts.width("café", 40.0)).abs() < 0.01);
+    let line = crate::layout::TextLine {
+        text: "e\u{301}".to_string(),
+        x: 10.0,
+        y: 0.0,
+        scale: 40.0,
+    };
+    assert!(fonts.glyphs(&line).iter().all(|g| g.position().x >= 10.0));
+    assert!(Fonts::new(Vec::new()).is_err());
+    assert!(Fonts::new(vec![b"not a font".as_slice()]).is_err());
+
+    // dark on light labels, in both raster and vector form
+    let mut dark_on_light = crate::template::builtin()["avery-l7160"].clone();
+    dark_on_light.layout.style = LabelStyle::DarkOnLight;
+    let figment = rocket::Config::figment()
+        .merge(("label_templates.light", &dark_on_light))
+        .merge(("labels.fallback_fonts", ["assets/iosevka-regular.ttf"]));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Réserve 倉庫 مخزن" }"#)
+        .dispatch();
+    let corner = |template: &str| {
+        let response = client
+            .get(format!("/container/qr/1?template={}", template))
+            .dispatch();
+        let png = image::load_from_memory(&response.into_bytes().unwrap())
+            .expect("Valid PNG")
+            .to_luma8();
+        png.get_pixel(png.width() - 1, png.height() - 1).0[0]
+    };
+    assert_eq!(corner("avery-l7160"), 0);
+    assert_eq!(corner("light"), 255);
+    assert_eq!(corner("zebra-2x1"), 255);
+
+    let state = crate::AppState {
+        root_url: String::from("http://localhost:8000"),
+        templates: crate::template::builtin(),
+        labels: Default::default(),
+        fonts,
+        renderer: Renderer::new(&Default::default()),
+        blobs: BlobStore::open(&Default::default()).unwrap(),
+    };
+    let label = LabelData::new(1, "container", "Toolchest".to_string());
+    let (width, height) = dark_on_light.label_pixels();
+    let layout =
+        crate::layout::layout(&state.fonts, &label, &dark_on_light.layout, width, height);
+    let paths =
+        crate::vector::label_paths(&state, &label, &dark_on_light.layout, &layout).unwrap();
+    let lumas: Vec<u8> = paths.iter().map(|path| path.luma).collect();
+    assert_eq!(lumas, [255, 255, 0, 0]);
+
+    // fonts that cannot be loaded stop the server from starting
+    for fallback in ["/no/such/font.ttf", "Cargo.toml"] {
+        let figment = rocket::Config::figment().merge(("labels.fallback_fonts", [fallback]));
+        let error =
+            Client::tracked(rocket().configure(figment)).expect_err("bad fallback font rejected");
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+    }
+}
+
+#[test]
+fn test_label_rendering() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+
+    let etag = |response: &LocalResponse| response.headers().get_one("ETag").unwrap().to_string();
+    let response = client.get("/container/qr/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("no-cache"));
+    let first = etag(&response);
+    let png = response.into_bytes().unwrap();
+
+    // an unchanged label is served again, or not at all if the client has it
+    let response = client.get("/container/qr/1").dispatch();
+    assert_eq!(etag(&response), first);
+    assert_eq!(response.into_bytes().unwrap(), png);
+    let response = client
+        .get("/container/qr/1")
+        .header(Header::new("If-None-Match", first.clone()))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotModified);
+    assert!(response.into_bytes().unwrap_or_default().is_empty());
+
+    // each format and template is its own label
+    let response = client.get("/container/svg/1").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::SVG));
+    assert_ne!(etag(&response), first);
+    let response = client.get("/container/qr/1?template=avery-l7160").dispatch();
+    assert_ne!(etag(&response), first);
+
+    // renaming the container changes its label
+    client
+        .put("/container/1")
+        .header(ContentType::JSON)
+        .body(r#"{ "parent_container_id": null, "name": "Top shelf", "note": null, "photo": null }"#)
+        .dispatch();
+    let response = client
+        .get("/container/qr/1")
+        .header(Header::new("If-None-Match", first.clone()))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_ne!(etag(&response), first);
+
+    let response = client.get("/container/qr").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PDF));
+    let sheet = etag(&response);
+    let response = client
+        .get("/container/qr")
+        .header(Header::new("If-None-Match", format!("W/{}", sheet)))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotModified);
+
+    // labels that cannot be drawn are a bad request, not a crash
+    let response = client
+        .post("/labels")
+        .header(ContentType::JSON)
+        .body(r#"{ "ids": [{ "kind": "container", "id": 1 }], "symbology": "micro_qr", "ec_level": "high" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // rendering one at a time without keeping anything still works
+    let figment = rocket::Config::figment()
+        .merge(("labels.max_renders", 1))
+        .merge(("labels.cache_size", 0));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    let first = client.get("/container/qr/1").dispatch();
+    let second = client.get("/container/qr/1").dispatch();
+    assert_eq!(etag(&first), etag(&second));
+
+    // as does rendering labels too big to keep
+    let figment = rocket::Config::figment().merge(("labels.cache_bytes", "1 KiB"));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    let first = client.get("/container/qr").dispatch();
+    assert_eq!(first.status(), Status::Ok);
+    let second = client.get("/container/qr").dispatch();
+    assert_eq!(etag(&first), etag(&second));
+
+    let figment = rocket::Config::figment().merge(("labels.max_renders", 0));
+    let error =
+        Client::tracked(rocket().configure(figment)).expect_err("max_renders of 0 rejected");
+    assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+}
+
+/// A multipart form with one file field, after some text fields.
+fn multipart(
+    name: &str,
+    file_name: &str,
+    data: &[u8],
+    fields: &[(&str, &str)],
+) -> (ContentType, Vec<u8>) {
+    let boundary = "photo-boundary";
+    let mut body = String::new();
+    for (field, value) in fields {
+        body += &format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            boundary, field, value
+        );
+    }
+    let mut body = body.into_bytes();
+    body.extend(format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n",
+        boundary, name, file_name
+    )
+    .into_bytes());
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let content_type =
+        ContentType::new("multipart", "form-data").with_params(("boundary", boundary));
+    (content_type, body)
+}
+
+#[test]
+fn test_photos() {
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    assert_eq!(
+        client.get("/container/1/photo").dispatch().status(),
+        Status::NotFound
+    );
+
+    let mut png = Vec::new();
+    image::GrayImage::from_pixel(8, 8, image::Luma([128]))
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    let upload = |url: &str, data: &[u8]| {
+        let (content_type, body) = multipart("photo", "toolchest.png", data, &[]);
+        client
+            .post(url)
+            .header(content_type)
+            .body(body)
+            .dispatch()
+            .status()
+    };
+    assert_eq!(upload("/container/1/photo", &png), Status::Ok);
+    assert_eq!(upload("/container/2/photo", &png), Status::NotFound);
+    // the type comes from the image, not the upload
+    let response = client.get("/container/1/photo").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert_eq!(response.into_bytes(), Some(png.clone()));
+
+    // photos stay out of the JSON, and survive updates that do not mention them
+    let response = client.get("/container/1").dispatch();
+    assert!(!response.into_string().unwrap().contains("photo"));
+    client
+        .put("/container/1")
+        .header(ContentType::JSON)
+        .body(r#"{ "parent_container_id": null, "name": "Top shelf", "note": null }"#)
+        .dispatch();
+    let response = client.get("/container/1/photo").dispatch();
+    assert_eq!(response.into_bytes(), Some(png.clone()));
+
+    // pages link to the photo rather than inlining it
+    let response = client.get("/container/1").header(Accept::HTML).dispatch();
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains(r#"<a href="/photo/1"><img src="/photo/1?size=medium" alt=""></a>"#));
+
+    assert_eq!(
+        upload("/container/1/photo", b"not an image"),
+        Status::UnprocessableEntity
+    );
+    assert_eq!(
+        client.delete("/container/1/photo").dispatch().status(),
+        Status::Ok
+    );
+    assert_eq!(
+        client.get("/container/1/photo").dispatch().status(),
+        Status::NotFound
+    );
+
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "M3 Bolt, 20mm" }"#)
+        .dispatch();
+    assert_eq!(upload("/item/1/photo", &png), Status::Ok);
+    let response = client.get("/item/1/photo").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    let response = client.get("/item/1").header(Accept::HTML).dispatch();
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains(r#"src="/photo/2?size=medium""#));
+
+    // photos over the limit are turned away
+    let figment = rocket::Config::figment().merge(("limits.photo", 64));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "M3 Bolt, 20mm" }"#)
+        .dispatch();
+    let (content_type, body) = multipart("photo", "big.png", &[0; 100], &[]);
+    let response = client
+        .post("/item/1/photo")
+        .header(content_type)
+        .body(body)
+        .dispatch();
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+}
+
+/// A JPEG of a `width` by `height` image, tagged to be turned a quarter
+/// clockwise to be seen upright - EXIF orientation 6.
+fn rotated_jpeg(width: u32, height: u32) -> Vec<u8> {
+    let mut jpeg = Vec::new();
+    image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]))
+        .write_to(
+            &mut Cursor::new(&mut jpeg),
+            image::ImageOutputFormat::Jpeg(90),
+        )
+        .unwrap();
+    // a big-endian TIFF header and one IFD holding just the orientation
+    let mut app1 = b"\xff\xe1\x00\x22Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+    app1.extend_from_slice(b"\x00\x01\x01\x12\x00\x03\x00\x00\x00\x01\x00\x06\x00\x00");
+    app1.extend_from_slice(b"\0\0\0\0");
+    jpeg.splice(2..2, app1);
+    jpeg
+}
+
+#[test]
+fn test_photo_gallery() {
+    use crate::photo::PhotoInfo;
+
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "M3 Bolt, 20mm" }"#)
+        .dispatch();
+    let upload = |url: &'static str, data: &[u8], fields: &[(&str, &str)]| {
+        let (content_type, body) = multipart("photo", "bolt.jpg", data, fields);
+        client.post(url).header(content_type).body(body).dispatch()
+    };
+
+    let mut png = Vec::new();
+    image::GrayImage::from_pixel(8, 8, image::Luma([128]))
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    let response = upload("/item/1/photos", &png, &[("caption", "Head")]);
+    assert_eq!(response.status(), Status::Created);
+    let first = response.into_json::<PhotoInfo>().unwrap();
+    // the first photo is the primary one
+    assert!(first.primary);
+    assert_eq!(first.caption.as_deref(), Some("Head"));
+    assert_eq!((first.width, first.height), (Some(8), Some(8)));
+
+    let jpeg = rotated_jpeg(400, 200);
+    let response = upload("/item/1/photos", &jpeg, &[("primary", "true")]);
+    let second = response.into_json::<PhotoInfo>().unwrap();
+    assert!(second.primary);
+    // sizes are upright
+    assert_eq!((second.width, second.height), (Some(200), Some(400)));
+    assert_eq!(
+        upload("/item/2/photos", &png, &[]).status(),
+        Status::NotFound
+    );
+
+    let photos = client
+        .get("/item/1/photos")
+        .dispatch()
+        .into_json::<Vec<PhotoInfo>>()
+        .unwrap();
+    let ids: Vec<(i64, bool)> = photos.iter().map(|p| (p.id, p.primary)).collect();
+    assert_eq!(ids, vec![(second.id, true), (first.id, false)]);
+    let response = client.get("/item/1/photo").dispatch();
+    assert_eq!(response.into_bytes(), Some(jpeg.clone()));
+
+    // originals are kept as uploaded, thumbnails are upright JPEGs that fit
+    let response = client.get(format!("/photo/{}", second.id)).dispatch();
+    assert_eq!(
+        response.headers().get_one("Cache-Control"),
+        Some("max-age=31536000, immutable")
+    );
+    assert_eq!(response.into_bytes(), Some(jpeg));
+    for (size, dimensions) in [("small", (80, 160)), ("medium", (200, 400))] {
+        let response = client
+            .get(format!("/photo/{}?size={}", second.id, size))
+            .dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JPEG));
+        let thumbnail = image::load_from_memory(&response.into_bytes().unwrap()).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), dimensions);
+    }
+    let response = client
+        .get(format!("/photo/{}?size=small", first.id))
+        .dispatch();
+    let thumbnail = image::load_from_memory(&response.into_bytes().unwrap()).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (8, 8));
+
+    // photos given as JSON get their thumbnails when first asked for
+    client
+        .put("/item/1")
+        .header(ContentType::JSON)
+        .body(format!(
+            r#"{{ "name": "M3 Bolt, 20mm", "photo": {:?} }}"#,
+            png
+        ))
+        .dispatch();
+    let primary = client
+        .get("/item/1/photos")
+        .dispatch()
+        .into_json::<Vec<PhotoInfo>>()
+        .unwrap()
+        .remove(0);
+    assert_eq!(primary.width, None);
+    let response = client.get("/item/1/photo?size=large").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::JPEG));
+    assert_eq!(
+        client.delete("/item/1/photo").dispatch().status(),
+        Status::Ok
+    );
+
+    // the oldest photo left takes over from a deleted primary photo
+    let response = client
+        .put(format!("/photo/{}", first.id))
+        .header(ContentType::JSON)
+        .body(r#"{ "caption": "Thread", "primary": true }"#)
+        .dispatch();
+    let updated = response.into_json::<PhotoInfo>().unwrap();
+    assert!(updated.primary);
+    assert_eq!(updated.caption.as_deref(), Some("Thread"));
+    assert_eq!(
+        client
+            .delete(format!("/photo/{}", first.id))
+            .dispatch()
+            .status(),
+        Status::Ok
+    );
+    let photos = client
+        .get("/item/1/photos")
+        .dispatch()
+        .into_json::<Vec<PhotoInfo>>()
+        .unwrap();
+    assert_eq!(photos.len(), 1);
+    assert_eq!((photos[0].id, photos[0].primary), (second.id, true));
+
+    // item locations have galleries too, which go when they do
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    client
+        .post("/itemloc")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "quantity": 10 }"#)
+        .dispatch();
+    let response = upload("/itemloc/1/photos", &png, &[]);
+    let location = response.into_json::<PhotoInfo>().unwrap();
+    assert!(location.primary);
+    client.delete("/itemloc/1").dispatch();
+    assert_eq!(
+        client
+            .get(format!("/photo/{}", location.id))
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+    client.delete("/item/1").dispatch();
+    assert_eq!(
+        client
+            .get(format!("/photo/{}", second.id))
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+}
+
+#[test]
+fn test_blob_store() {
+    use rocket_db_pools::sqlx::{self, Connection};
+
+    let dir = std::env::temp_dir().join(format!("yvonne-test-{}", rand::random::<u32>()));
+    let db = dir.join("db.sqlite");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::File::create(&db).unwrap();
+    let figment = rocket::Config::figment()
+        .merge(("databases.testdb.url", db.to_str().unwrap()))
+        .merge(("blobs.path", dir.join("blobs")))
+        .merge(("blobs.gc_grace", 0));
+    let client =
+        Client::tracked(rocket().configure(figment.clone())).expect("valid rocket instance");
+    for name in ["M3 Bolt, 20mm", "M3 Bolt, 30mm"] {
+        client
+            .post("/item")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "{}" }}"#, name))
+            .dispatch();
+    }
+
+    let mut png = Vec::new();
+    image::GrayImage::from_pixel(8, 8, image::Luma([128]))
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    for item in [1, 2] {
+        let (content_type, body) = multipart("photo", "bolt.png", &png, &[]);
+        client
+            .post(format!("/item/{}/photos", item))
+            .header(content_type)
+            .body(body)
+            .dispatch();
+    }
+    // the same photo is stored once, named by its hash
+    let hash = crate::blob::hash(&png);
+    let path = dir.join("blobs").join(&hash[..2]).join(&hash);
+    assert_eq!(std::fs::read(&path).unwrap(), png);
+
+    let gc = |client: &Client| {
+        client
+            .post("/blobs/gc")
+            .dispatch()
+            .into_json::<Collected>()
+            .unwrap()
+    };
+    assert_eq!(gc(&client).blobs, 0);
+    client.delete("/photo/1").dispatch();
+    assert_eq!(gc(&client).blobs, 0);
+    // the photo and its thumbnail, which is the same at every size
+    client.delete("/item/2").dispatch();
+    let collected = gc(&client);
+    assert_eq!(collected.blobs, 2);
+    assert!(collected.bytes > png.len() as u64);
+    assert!(!path.exists());
+
+    // photos kept in the database before the store are moved into it
+    let (content_type, body) = multipart("photo", "bolt.png", &png, &[]);
+    client
+        .post("/item/1/photos")
+        .header(content_type)
+        .body(body)
+        .dispatch();
+    drop(client);
+    rocket::tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async {
+            let mut conn = sqlx::SqliteConnection::connect(db.to_str().unwrap())
+                .await
+                .unwrap();
+            sqlx::query("UPDATE photo SET data = ?, hash = NULL")
+                .bind(&png)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        });
+    std::fs::remove_dir_all(dir.join("blobs")).unwrap();
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    let response = client.get("/item/1/photo").dispatch();
+    assert_eq!(response.into_bytes(), Some(png.clone()));
+    assert!(path.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_attachments() {
+    use crate::attachment::Attachment;
+
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Rigol DS1054Z" }"#)
+        .dispatch();
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Instrument Shelf" }"#)
+        .dispatch();
+    client
+        .post("/unit")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "serial_number": "DS1ZA1234", "purchase_date": "2021-03-14", "condition": "good" }"#)
+        .dispatch();
+    let attach = |url: &'static str, file_name: &str, data: &[u8]| {
+        let (content_type, body) = multipart("file", file_name, data, &[]);
+        client.post(url).header(content_type).body(body).dispatch()
+    };
+
+    let datasheet = b"%PDF-1.4 datasheet";
+    let response = attach("/item/1/attachments", "DS1000Z Datasheet.pdf", datasheet);
+    assert_eq!(response.status(), Status::Created);
+    let attachment = response.into_json::<Attachment>().unwrap();
+    assert_eq!(attachment.file_name, "DS1000Z Datasheet.pdf");
+    // the type comes from the extension when the upload does not say
+    assert_eq!(attachment.content_type, "application/pdf");
+    assert_eq!(attachment.size, datasheet.len() as i64);
+    assert_eq!(
+        attach("/item/2/attachments", "manual.pdf", datasheet).status(),
+        Status::NotFound
+    );
+
+    let response = client
+        .get(format!("/attachment/{}", attachment.id))
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PDF));
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some(
+            "inline; filename=\"DS1000Z Datasheet.pdf\"; filename*=UTF-8''DS1000Z%20Datasheet.pdf"
+        )
+    );
+    assert_eq!(response.into_bytes(), Some(datasheet.to_vec()));
+
+    // paths are dropped from names, and anything but PDFs and images is saved
+    // rather than shown
+    let response = attach(
+        "/unit/1/attachments",
+        "C:\\receipts\\receipt.html",
+        b"<p>Paid</p>",
+    );
+    let receipt = response.into_json::<Attachment>().unwrap();
+    assert_eq!(receipt.file_name, "receipt.html");
+    let response = client.get(format!("/attachment/{}", receipt.id)).dispatch();
+    assert!(response
+        .headers()
+        .get_one("Content-Disposition")
+        .unwrap()
+        .starts_with("attachment;"));
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+    attach("/container/1/attachments", "shelf.stl", b"solid shelf");
+
+    let attachments = client
+        .get("/item/1/attachments")
+        .dispatch()
+        .into_json::<Vec<Attachment>>()
+        .unwrap();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].id, attachment.id);
+    assert_eq!(
+        client
+            .delete(format!("/attachment/{}", attachment.id))
+            .dispatch()
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(
+        client
+            .get(format!("/attachment/{}", attachment.id))
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+
+    // attachments go with what they are attached to
+    client.delete("/unit/1").dispatch();
+    assert_eq!(
+        client
+            .get(format!("/attachment/{}", receipt.id))
+            .dispatch()
+            .status(),
+        Status::NotFound
+    );
+    let attachments = client
+        .get("/container/1/attachments")
+        .dispatch()
+        .into_json::<Vec<Attachment>>()
+        .unwrap();
+    assert_eq!(attachments[0].file_name, "shelf.stl");
+
+    // files over the limit are turned away
+    let figment = rocket::Config::figment().merge(("limits.attachment", 64));
+    let client = Client::tracked(rocket().configure(figment)).expect("valid rocket instance");
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Rigol DS1054Z" }"#)
+        .dispatch();
+    let (content_type, body) = multipart("file", "manual.pdf", &[0; 100], &[]);
+    let response = client
+        .post("/item/1/attachments")
+        .header(content_type)
+        .body(body)
+        .dispatch();
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+}
+
+/// A PNG of a 9 by 8 grid of random shades, `scale` pixels to a cell - a
+/// stand-in for a photo of a part.
+fn pattern(seed: u32, scale: u32, brighten: u8) -> Vec<u8> {
+    let mut state = seed;
+    let mut shades = Vec::new();
+    for _ in 0..72 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        shades.push((state >> 16) as u8 / 2 + brighten);
+    }
+    let image = image::GrayImage::from_fn(9 * scale, 8 * scale, |x, y| {
+        image::Luma([shades[(y / scale * 9 + x / scale) as usize]])
+    });
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    png
+}
+
+#[test]
+fn test_search_by_photo() {
+    use crate::similar::SimilarItem;
+
+    let client = Client::tracked(rocket()).expect("valid rocket instance");
+    client
+        .post("/container")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Toolchest" }"#)
+        .dispatch();
+    for name in ["M3 Bolt, 20mm", "Hex Standoff"] {
+        client
+            .post("/item")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{ "name": "{}" }}"#, name))
+            .dispatch();
+    }
+    client
+        .post("/itemloc")
+        .header(ContentType::JSON)
+        .body(r#"{ "item_id": 1, "container_id": 1, "quantity": 10 }"#)
+        .dispatch();
+    let (content_type, body) = multipart("photo", "bolt.png", &pattern(1, 8, 0), &[]);
+    client
+        .post("/item/1/photos")
+        .header(content_type)
+        .body(body)
+        .dispatch();
+    // photos given as JSON are hashed when first searched, and ones that are
+    // not images are passed over
+    client
+        .post("/item")
+        .header(ContentType::JSON)
+        .body(format!(
+            r#"{{ "name": "Spacer", "photo": {:?} }}"#,
+            pattern(2, 8, 0)
+        ))
+        .dispatch();
+    client
+        .put("/item/2")
+        .header(ContentType::JSON)
+        .body(r#"{ "name": "Hex Standoff", "photo": [0, 1, 2, 3, 4] }"#)
+        .dispatch();
+
+    let search = |photo: Vec<u8>| {
+        client
+            .post("/item/search-by-photo")
+            .body(photo)
+            .dispatch()
+            .into_json::<Vec<SimilarItem>>()
+            .unwrap()
+    };
+    // bigger and brighter, as a photo on the bench would be
+    let found = search(pattern(1, 20, 60));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].item_id, 1);
+    assert_eq!(found[0].name, "M3 Bolt, 20mm");
+    assert_eq!(found[0].distance, 0);
+    assert_eq!(found[0].locations, vec!["Toolchest".to_string()]);
+
+    let found = search(pattern(2, 8, 0));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].item_id, 3);
+
+    // photos taken at a quarter turn
+    let turned = image::load_from_memory(&pattern(1, 8, 0))
+        .unwrap()
+        .rotate90();
+    let mut png = Vec::new();
+    turned
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    assert_eq!(search(png)[0].item_id, 1);
+
+    assert!(search(pattern(3, 8, 0)).is_empty());
+    let response = client
+        .post("/item/search-by-photo?max_distance=64&limit=1")
+        .body(pattern(3, 8, 0))
+        .dispatch();
+    assert_eq!(response.into_json::<Vec<SimilarItem>>().unwrap().len(), 1);
+    let response = client
+        .post("/item/search-by-photo")
+        .body("not an image")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
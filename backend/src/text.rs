@@ -0,0 +1,165 @@
+use printpdf::image_crate::{GrayImage, Luma};
+use rusttype::{point, Font, GlyphId, PositionedGlyph, Scale};
+
+use crate::layout::TextLine;
+
+/// Iosevka, which labels are printed in unless other fonts are configured.
+static BUILTIN: &[u8] = include_bytes!("../assets/iosevka-regular.ttf");
+
+/// One font, parsed once for drawing and once for shaping.
+struct Face {
+    font: Font<'static>,
+    shaper: rustybuzz::Face<'static>,
+}
+
+impl Face {
+    fn parse(data: &'static [u8]) -> Option<Self> {
+        Some(Face {
+            font: Font::try_from_bytes(data)?,
+            shaper: rustybuzz::Face::from_slice(data, 0)?,
+        })
+    }
+
+    fn has(&self, c: char) -> bool {
+        self.shaper.glyph_index(c).is_some()
+    }
+
+    /// Font units to pixels at `scale`, matching how rusttype scales.
+    fn units_to_pixels(&self, scale: f32) -> f32 {
+        let metrics = self.font.v_metrics_unscaled();
+        scale / (metrics.ascent - metrics.descent)
+    }
+}
+
+/// A glyph from shaping a line, positioned relative to the start of its
+/// baseline in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Shaped {
+    face: usize,
+    id: u16,
+    x: f32,
+    y: f32,
+}
+
+/// The fonts labels are printed in - a primary font and fallbacks. Each
+/// character is drawn in the first font that has it, and runs of text in
+/// the same font are shaped together so that joining scripts, combining
+/// marks and right to left text come out right. Runs are laid out left to
+/// right in the order they are written.
+pub struct Fonts {
+    faces: Vec<Face>,
+}
+
+impl Fonts {
+    /// Just the builtin font.
+    pub fn builtin() -> Self {
+        Fonts::new(vec![BUILTIN]).expect("Failed to decode font!")
+    }
+
+    /// A font chain from font file contents, the primary font first.
+    pub fn new(data: Vec<&'static [u8]>) -> Result<Self, String> {
+        let faces = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| Face::parse(data).ok_or(format!("font {} is not usable", i + 1)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if faces.is_empty() {
+            return Err("at least one font is needed".into());
+        }
+        Ok(Fonts { faces })
+    }
+
+    /// The builtin font's data, for chains that fall back to it.
+    pub fn builtin_data() -> &'static [u8] {
+        BUILTIN
+    }
+
+    /// Which face to draw `c` in, preferring to stay in the current run's
+    /// face for spaces, punctuation and marks so they are shaped along with
+    /// the text around them.
+    fn face_for(&self, c: char, current: Option<usize>) -> usize {
+        match current {
+            Some(face) if !c.is_alphanumeric() && self.faces[face].has(c) => face,
+            // characters no font has are drawn as the primary font's box
+            _ => self.faces.iter().position(|f| f.has(c)).unwrap_or(0),
+        }
+    }
+
+    /// Shape a line of text at a pixel size.
+    fn shape(&self, text: &str, scale: f32) -> (Vec<Shaped>, f32) {
+        // split the text into runs that each need a single face
+        let mut runs: Vec<(usize, String)> = Vec::new();
+        for c in text.chars() {
+            let face = self.face_for(c, runs.last().map(|(face, _)| *face));
+            match runs.last_mut() {
+                Some((current, run)) if *current == face => run.push(c),
+                _ => runs.push((face, c.to_string())),
+            }
+        }
+
+        let mut glyphs = Vec::new();
+        let mut x = 0.0;
+        for (face, run) in runs {
+            let pixels = self.faces[face].units_to_pixels(scale);
+            let mut buffer = rustybuzz::UnicodeBuffer::new();
+            buffer.push_str(&run);
+            let shaped = rustybuzz::shape(&self.faces[face].shaper, &[], buffer);
+            for (info, position) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+                glyphs.push(Shaped {
+                    face,
+                    id: info.glyph_id as u16,
+                    x: x + position.x_offset as f32 * pixels,
+                    // font units point up, pixels down
+                    y: -position.y_offset as f32 * pixels,
+                });
+                x += position.x_advance as f32 * pixels;
+            }
+        }
+        (glyphs, x)
+    }
+
+    /// How wide a line of text is at a pixel size.
+    pub fn width(&self, text: &str, scale: f32) -> f32 {
+        self.shape(text, scale).1
+    }
+
+    /// The glyphs of a line of text, with the top left of its line box at
+    /// `(x, y)`.
+    pub fn glyphs(&self, line: &TextLine) -> Vec<PositionedGlyph<'_>> {
+        let scale = Scale::uniform(line.scale);
+        let baseline = line.y + self.faces[0].font.v_metrics(scale).ascent;
+        let (glyphs, _) = self.shape(&line.text, line.scale);
+        glyphs
+            .into_iter()
+            .map(|glyph| {
+                self.faces[glyph.face]
+                    .font
+                    .glyph(GlyphId(glyph.id))
+                    .scaled(scale)
+                    .positioned(point(line.x + glyph.x, baseline + glyph.y))
+            })
+            .collect()
+    }
+
+    /// Draw a line of text onto an image in shade `luma`, blending its
+    /// antialiased edges into what is underneath.
+    pub fn draw(&self, image: &mut GrayImage, line: &TextLine, luma: u8) {
+        for glyph in self.glyphs(line) {
+            let bounds = match glyph.pixel_bounding_box() {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            glyph.draw(|gx, gy, coverage| {
+                let x = bounds.min.x + gx as i32;
+                let y = bounds.min.y + gy as i32;
+                if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+                    return;
+                }
+                let pixel = image.get_pixel_mut(x as u32, y as u32);
+                let under = pixel.0[0] as f32;
+                let blended = under + (luma as f32 - under) * coverage.min(1.0);
+                *pixel = Luma([blended.round() as u8]);
+            });
+        }
+    }
+}
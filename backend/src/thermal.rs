@@ -0,0 +1,142 @@
+use std::fmt::Write;
+
+use printpdf::image_crate::GrayImage;
+use rocket::http::ContentType;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::template::LabelTemplate;
+
+/// Raw formats thermal label printers accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, FromFormField)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ThermalFormat {
+    /// Zebra Programming Language, for Zebra and compatible printers
+    Zpl,
+    /// Brother QL raster commands, for continuous tape
+    #[field(value = "brother_ql")]
+    BrotherQl,
+}
+
+/// Pixels darker than this are printed.
+const THRESHOLD: u8 = 128;
+
+/// Pack one row of a label into bits, most significant bit first, with 1 for
+/// a printed dot.
+fn pack_row(image: &GrayImage, y: u32) -> Vec<u8> {
+    let mut row = vec![0u8; (image.width() as usize).div_ceil(8)];
+    for x in 0..image.width() {
+        if image.get_pixel(x, y).0[0] < THRESHOLD {
+            row[x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    row
+}
+
+/// One ZPL format per label, each printing the label as a graphic field.
+pub fn zpl(labels: &[GrayImage]) -> Vec<u8> {
+    let mut zpl = String::new();
+    for label in labels {
+        let bytes_per_row = (label.width() as usize).div_ceil(8);
+        let total = bytes_per_row * label.height() as usize;
+        write!(
+            zpl,
+            "^XA\n^PW{}\n^LL{}\n^FO0,0^GFA,{},{},{},",
+            label.width(),
+            label.height(),
+            total,
+            total,
+            bytes_per_row
+        )
+        .unwrap();
+        for y in 0..label.height() {
+            for byte in pack_row(label, y) {
+                write!(zpl, "{:02X}", byte).unwrap();
+            }
+        }
+        zpl.push_str("^FS\n^XZ\n");
+    }
+    zpl.into_bytes()
+}
+
+/// Dots across the print head of the QL-500 to QL-820 series.
+const QL_HEAD_DOTS: u32 = 720;
+
+/// Continuous tapes by width in millimetres, with how many dots of the head
+/// print on them and how many are left blank on the right.
+const QL_TAPES: [(u8, u32, u32); 6] = [
+    (12, 106, 29),
+    (29, 306, 6),
+    (38, 413, 12),
+    (50, 554, 12),
+    (54, 590, 0),
+    (62, 696, 12),
+];
+
+/// A print job for a Brother QL printer with continuous tape, one page per
+/// label, cutting after each. The tape is the template's page width; labels
+/// are centred across its printable area.
+pub fn brother_ql(labels: &[GrayImage], template: &LabelTemplate) -> Result<Vec<u8>, String> {
+    let tape_width = template.page_width.round() as u8;
+    let &(_, printable, right_margin) = QL_TAPES
+        .iter()
+        .find(|(width, _, _)| *width == tape_width)
+        .ok_or(format!(
+            "no {}mm continuous tape for Brother QL",
+            tape_width
+        ))?;
+
+    let mut job = vec![0u8; 200]; // invalidate whatever the printer was doing
+    job.extend([0x1b, b'@']); // initialize
+    job.extend([0x1b, b'i', b'a', 0x01]); // raster mode
+    for (page, label) in labels.iter().enumerate() {
+        if label.width() > printable {
+            return Err(format!(
+                "labels are {} dots wide but only {} print on {}mm tape",
+                label.width(),
+                printable,
+                tape_width
+            ));
+        }
+
+        // media type and width are given - continuous tape has no length
+        job.extend([0x1b, b'i', b'z', 0x86, 0x0a, tape_width, 0x00]);
+        job.extend(label.height().to_le_bytes());
+        job.extend([(page > 0) as u8, 0x00]);
+        job.extend([0x1b, b'i', b'M', 0x40]); // auto cut
+        job.extend([0x1b, b'i', b'A', 0x01]); // after every label
+        job.extend([0x1b, b'i', b'K', 0x08]); // and after the last one
+        job.extend([0x1b, b'i', b'd', 35, 0x00]); // feed margin in dots
+        job.extend([b'M', 0x00]); // no compression
+
+        // the head prints right to left, so rows are sent mirrored
+        let right = right_margin + (printable - label.width()) / 2;
+        for y in 0..label.height() {
+            let mut row = [0u8; QL_HEAD_DOTS as usize / 8];
+            for x in 0..label.width() {
+                if label.get_pixel(x, y).0[0] < THRESHOLD {
+                    let dot = (right + label.width() - 1 - x) as usize;
+                    row[dot / 8] |= 0x80 >> (dot % 8);
+                }
+            }
+            job.extend([b'g', 0x00, row.len() as u8]);
+            job.extend(row);
+        }
+
+        // print and feed - 0x1a marks the last page
+        job.push(if page + 1 == labels.len() { 0x1a } else { 0x0c });
+    }
+
+    Ok(job)
+}
+
+/// Encode labels in `format`, with the content type to send them as.
+pub fn encode(
+    labels: &[GrayImage],
+    template: &LabelTemplate,
+    format: ThermalFormat,
+) -> Result<(ContentType, Vec<u8>), String> {
+    match format {
+        ThermalFormat::Zpl => Ok((ContentType::Plain, zpl(labels))),
+        ThermalFormat::BrotherQl => Ok((ContentType::Binary, brother_ql(labels, template)?)),
+    }
+}
@@ -0,0 +1,221 @@
+use crate::rocket::futures::{TryFutureExt, TryStreamExt};
+use rocket::State;
+use rocket_db_pools::sqlx::{self, SqliteConnection};
+use rocket_db_pools::Connection;
+use std::sync::Arc;
+
+use crate::layout::LabelData;
+use crate::render::{Output, RenderError, Rendered};
+use crate::thermal::ThermalFormat;
+use crate::AppState;
+use crate::Db;
+
+use rocket::response::status::Created;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+
+type Result<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+/// An individual, serialized unit of an item type - e.g. the oscilloscope with
+/// serial number DS1ZA1234, as opposed to the "Rigol DS1054Z" item.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Unit {
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub item_id: i64,
+    pub container_id: i64,
+    pub serial_number: String,
+    /// ISO 8601 date, e.g. "2022-11-30"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purchase_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Short code printed on labels, e.g. "U-7K2P" - see `short_code`.
+    /// Assigned by the server; ignored when given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+#[post("/unit", data = "<unit>")]
+pub async fn create(mut db: Connection<Db>, unit: Json<Unit>) -> Result<Created<Json<Unit>>> {
+    let result = sqlx::query!(
+        "INSERT INTO unit (item_id, container_id, serial_number, purchase_date, condition, note)
+        VALUES (?, ?, ?, ?, ?, ?)",
+        unit.item_id,
+        unit.container_id,
+        unit.serial_number,
+        unit.purchase_date,
+        unit.condition,
+        unit.note
+    )
+    .execute(&mut *db)
+    .await?;
+    crate::short_code::assign(&mut db, "unit", result.last_insert_rowid()).await?;
+
+    Ok(Created::new("/").body(unit))
+}
+
+/// A unit by id, with its short code.
+pub async fn fetch(db: &mut Connection<Db>, id: i64) -> Option<Unit> {
+    let mut unit = sqlx::query!(
+        "SELECT id, item_id, container_id, serial_number, purchase_date, condition, note
+        FROM unit WHERE id = ?",
+        id
+    )
+    .fetch_one(&mut **db)
+    .map_ok(|r| Unit {
+        id: Some(r.id),
+        item_id: r.item_id,
+        container_id: r.container_id,
+        serial_number: r.serial_number,
+        purchase_date: r.purchase_date,
+        condition: r.condition,
+        note: r.note,
+        code: None,
+    })
+    .await
+    .ok()?;
+    unit.code = crate::short_code::get(db, "unit", id).await.ok()?;
+    Some(unit)
+}
+
+#[get("/unit/<id>", rank = 2)]
+pub async fn read(mut db: Connection<Db>, id: i64) -> Option<Json<Unit>> {
+    fetch(&mut db, id).await.map(Json)
+}
+
+/// What goes on a unit's label.
+pub async fn label_data(conn: &mut SqliteConnection, id: i64) -> Result<LabelData, sqlx::Error> {
+    let unit = sqlx::query!(
+        "SELECT unit.id, unit.container_id, unit.serial_number, item.name
+        FROM unit JOIN item ON item.id = unit.item_id WHERE unit.id = ?",
+        id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let name = format!("{} S/N {}", unit.name, unit.serial_number);
+    let mut label = LabelData::new(unit.id, "unit", name);
+    label.code = crate::short_code::get(&mut *conn, "unit", id).await?;
+    let path = crate::container::path(&mut *conn, unit.container_id).await?;
+    label.path = Some(path.join(" / "));
+    Ok(label)
+}
+
+/// A label as an SVG drawing, for printing at any size without blurring.
+#[get("/unit/svg/<id>?<template>")]
+pub async fn read_svg(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    crate::render::label(state, label_data(&mut db, id).await, template, Output::Svg).await
+}
+
+/// A label for a thermal printer, as ZPL or Brother QL raster.
+#[get("/unit/thermal/<id>?<format>&<template>")]
+pub async fn read_thermal(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    format: ThermalFormat,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    let output = Output::Thermal(format);
+    crate::render::label(state, label_data(&mut db, id).await, template, output).await
+}
+
+#[get("/unit/qr/<id>?<template>")]
+pub async fn read_qr(
+    mut db: Connection<Db>,
+    state: &State<Arc<AppState>>,
+    id: i64,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    crate::render::label(state, label_data(&mut db, id).await, template, Output::Png).await
+}
+
+#[put("/unit/<id>", data = "<unit>")]
+pub async fn full_update(
+    mut db: Connection<Db>,
+    id: i64,
+    unit: Json<Unit>,
+) -> Result<Created<Json<Unit>>> {
+    sqlx::query!(
+        "UPDATE unit SET item_id=?, container_id=?, serial_number=?, purchase_date=?, condition=?,
+        note=? WHERE id = ?",
+        unit.item_id,
+        unit.container_id,
+        unit.serial_number,
+        unit.purchase_date,
+        unit.condition,
+        unit.note,
+        id
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(Created::new("/")) // TODO revisit this return
+}
+
+#[delete("/unit/<id>")]
+pub async fn delete(mut db: Connection<Db>, id: i64) -> Result<Option<()>> {
+    let result = sqlx::query!("DELETE FROM unit WHERE id = ?", id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+/// Units of one item type, or all units.
+#[get("/unit?<item_id>")]
+pub async fn list(mut db: Connection<Db>, item_id: Option<i64>) -> Result<Json<Vec<i64>>> {
+    let ids = sqlx::query!(
+        "SELECT id FROM unit WHERE ?1 IS NULL OR item_id = ?1",
+        item_id
+    )
+    .fetch(&mut *db)
+    .map_ok(|r| r.id.unwrap())
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(Json(ids))
+}
+
+#[get("/unit/qr?<template>")]
+pub async fn list_qr(
+    state: &State<Arc<AppState>>,
+    mut db: Connection<Db>,
+    template: Option<&str>,
+) -> Result<Option<Result<Rendered, RenderError>>> {
+    let template = match crate::template::find(state, template) {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let ids = sqlx::query!("SELECT id FROM unit")
+        .fetch(&mut *db)
+        .map_ok(|r| r.id.unwrap())
+        .try_collect::<Vec<_>>()
+        .await?;
+    let mut units = Vec::new();
+    for id in ids {
+        units.push(label_data(&mut db, id).await?);
+    }
+    Ok(Some(crate::render::labels(state, units, template, Output::Pdf).await))
+}
+
+/// Find a unit by its serial number, for scanning the manufacturer's
+/// serial number barcode.
+pub async fn find_by_serial(db: &mut Connection<Db>, serial_number: &str) -> Option<i64> {
+    sqlx::query!(
+        "SELECT id FROM unit WHERE serial_number = ? ORDER BY id",
+        serial_number
+    )
+    .fetch_one(&mut **db)
+    .map_ok(|r| r.id)
+    .await
+    .ok()
+}
@@ -0,0 +1,43 @@
+use rocket::data::{ByteUnit, Data};
+
+use crate::render::RenderError;
+
+/// Why an uploaded image could not be used.
+#[derive(Debug, Responder)]
+pub enum UploadError {
+    /// More than the limit for its kind of upload
+    #[response(status = 413)]
+    TooLarge(String),
+    /// Not an image, or not one that can be read
+    #[response(status = 400)]
+    Invalid(String),
+    /// Reading it crashed
+    #[response(status = 500)]
+    Failed(String),
+}
+
+/// Images are decoded on the label renderer's thread pool - see
+/// `render::run` - whose errors mean the same for them.
+impl From<RenderError> for UploadError {
+    fn from(e: RenderError) -> Self {
+        match e {
+            RenderError::Invalid(message) => UploadError::Invalid(message),
+            RenderError::Failed(message) => UploadError::Failed(message),
+        }
+    }
+}
+
+/// Read an image sent as a request body, refusing one over `limit` rather
+/// than decoding part of it.
+pub async fn read(data: Data<'_>, limit: ByteUnit) -> Result<Vec<u8>, UploadError> {
+    let image = data
+        .open(limit)
+        .into_bytes()
+        .await
+        .map_err(|e| UploadError::Invalid(e.to_string()))?;
+    if !image.is_complete() {
+        let message = format!("images must be under {}", limit);
+        return Err(UploadError::TooLarge(message));
+    }
+    Ok(image.into_inner())
+}
@@ -3,13 +3,10 @@ use std::io::Cursor;
 use rocket::http::ContentType;
 use rocket_db_pools::sqlx::{self, Row};
 use rocket_db_pools::Connection;
-use rocket::State;
 
 use crate::Db;
 use crate::AppState;
 
-use lazy_static::lazy_static;
-
 use rocket::response::status::Created;
 use rocket::serde::{json::Json, Deserialize, Serialize};
 
@@ -17,11 +14,6 @@ use rocket::serde::{json::Json, Deserialize, Serialize};
 //use image::ImageBuffer;
 //use image::{GrayImage};
 
-use imageproc::drawing::draw_text;
-
-use qrcode_generator::{QrCodeEcc, QRCodeError};
-
-use rusttype::{Font, Scale};
 extern crate printpdf;
 
 // imports the `image` library with the exact version that we are using
@@ -36,63 +28,64 @@ use printpdf::image_crate::Luma;
 use printpdf::image_crate::ImageBuffer;
 use printpdf::image_crate::{GrayImage};
 
-const QR_CODE_DIMENSION: usize = 300;
+use crate::layout::{LabelData, LabelLayout};
+use crate::symbol::Symbol;
+use crate::template::LabelTemplate;
 
-lazy_static! {
-    static ref FONT: Font<'static> = {
-        let font_data: &[u8] = include_bytes!("../assets/iosevka-regular.ttf");
-        Font::try_from_bytes(font_data).expect("Failed to decode font!")
-    };
+/// What a label's code encodes - a link to the entity through its short
+/// code, or straight to it for labels without one.
+pub fn qr_payload(state: &AppState, label: &LabelData) -> String {
+    match &label.code {
+        Some(code) => format!("{}/q/{}", state.root_url, code),
+        None => format!("{}/{}/{}", state.root_url, label.model_route, label.id),
+    }
 }
 
-pub fn generate_qr_code(state: &State<AppState>, id: i64, model_route: &str) -> Result<ImageBuffer<Luma<u8>, Vec<u8>>, QRCodeError> {
-    let url = format!("{}/{}/{}", state.root_url, model_route, id);
-    qrcode_generator::to_image_buffer(url, QrCodeEcc::Low, QR_CODE_DIMENSION)
+/// Encode a label's payload in the layout's symbology.
+pub fn encode_symbol(state: &AppState, label: &LabelData, label_layout: &LabelLayout) -> Result<Symbol, String> {
+    let payload = qr_payload(state, label);
+    Symbol::encode(&payload, label_layout.symbology, label_layout.ec_level)
 }
 
-pub fn generate_qr_label(state: &State<AppState>, id: i64, name: String, model_route: &str) -> ImageBuffer<Luma<u8>, Vec<u8>> {
-    let mut code = generate_qr_code(state, id, model_route).unwrap();
-    let mut label = GrayImage::new((QR_CODE_DIMENSION) as u32, (QR_CODE_DIMENSION as f32 * 1.25) as u32);
+/// Draw a label's code, filling a `width` by `height` image.
+pub fn generate_code(state: &AppState, label: &LabelData, label_layout: &LabelLayout, width: u32, height: u32) -> Result<GrayImage, String> {
+    encode_symbol(state, label, label_layout)?.to_image(width, height)
+}
 
-    printpdf::image_crate::imageops::overlay(&mut label, &code, 0, 0);
+/// Render a label at the size and resolution of one of `template`'s labels.
+pub fn generate_qr_label(state: &AppState, label: &LabelData, template: &LabelTemplate) -> Result<GrayImage, String> {
+    let (width, height) = template.label_pixels();
+    let layout = crate::layout::layout(&state.fonts, label, &template.layout, width, height);
+    let style = template.layout.style;
 
-    let x_scale = 6.0 / name.len() as f32 * 125.0;
+    let code = generate_code(state, label, &template.layout, layout.code_width, layout.code_height)?;
+    let mut image = GrayImage::from_pixel(width, height, Luma([style.background()]));
 
-    draw_text(&mut label, Luma { 0: [255] }, 0, QR_CODE_DIMENSION as i32 - 5, Scale { x: 84.0, y: 84.0 }, &FONT, name.as_str())
+    printpdf::image_crate::imageops::overlay(&mut image, &code, layout.code_x as i64, layout.code_y as i64);
+
+    for line in &layout.lines {
+        state.fonts.draw(&mut image, line, style.foreground());
+    }
+
+    Ok(image)
 }
 
-pub fn generate_qr_pdf(state: &State<AppState>, model_info: Vec<(i64, String)>, model_name: &str) -> Vec<u8> {
-    let (doc, page1, layer1) = PdfDocument::new("PDF_Document_title", Mm(210.0), Mm(297.0), "Layer 1");
-    let current_layer = doc.get_page(page1).get_layer(layer1);
-
-    let mut imx = Mm(0.76);
-    let mut imy = Mm(297.0 - 32.0);
-
-    let mut count = 0;
-    for (id, name) in model_info {
-        let label = crate::util::generate_qr_label(state, id, name, model_name);
-        let img = Image::from_dynamic_image(&label.into());
-        let transform = ImageTransform {
-            translate_x : Some(imx),
-            translate_y : Some(imy),
-            rotate : None,
-            scale_x : None,
-            scale_y : None,
-            dpi : Some(300.0)
-        };
-        img.add_to_layer(current_layer.clone(), transform);
-
-        imx += Mm(25.4);
-        imx += Mm(0.76);
-
-        count += 1;
-        if count % 8 == 0 {
-            imy -= Mm(32.0);
-            imy -= Mm(1.11);
-
-            imx = Mm(0.76);
+/// Lay labels out on as many sheets as they need, leaving the first `offset`
+/// slots of the first sheet empty.
+pub fn generate_qr_pdf(state: &AppState, labels: &[LabelData], template: &LabelTemplate, offset: usize) -> Result<Vec<u8>, String> {
+    let (page_width, page_height) = (Mm(template.page_width), Mm(template.page_height));
+    let (doc, page1, layer1) = PdfDocument::new("PDF_Document_title", page_width, page_height, "Layer 1");
+    let mut current_layer = doc.get_page(page1).get_layer(layer1);
+
+    for (count, label) in labels.iter().enumerate() {
+        let slot = (count + offset) % template.labels_per_page();
+        if count > 0 && slot == 0 {
+            let (page, layer) = doc.add_page(page_width, page_height, "Layer 1");
+            current_layer = doc.get_page(page).get_layer(layer);
         }
+
+        crate::vector::add_label_to_layer(state, &current_layer, label, template, template.position(slot))?;
     }
 
-    doc.save_to_bytes().unwrap()
+    doc.save_to_bytes().map_err(|e| format!("cannot save PDF: {}", e))
 }
@@ -0,0 +1,247 @@
+use std::fmt::Write;
+
+use printpdf::lopdf::content::Operation;
+use printpdf::{Mm, PdfLayerReference};
+use rusttype::OutlineBuilder;
+
+use crate::layout::{LabelData, LabelLayout, Layout};
+use crate::template::LabelTemplate;
+use crate::AppState;
+
+/// One piece of a path outline, in label pixels with y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    QuadTo(f32, f32, f32, f32),
+    CurveTo(f32, f32, f32, f32, f32, f32),
+    Close,
+}
+
+impl Segment {
+    fn translate(self, dx: f32, dy: f32) -> Self {
+        match self {
+            Segment::MoveTo(x, y) => Segment::MoveTo(x + dx, y + dy),
+            Segment::LineTo(x, y) => Segment::LineTo(x + dx, y + dy),
+            Segment::QuadTo(x1, y1, x, y) => Segment::QuadTo(x1 + dx, y1 + dy, x + dx, y + dy),
+            Segment::CurveTo(x1, y1, x2, y2, x, y) => {
+                Segment::CurveTo(x1 + dx, y1 + dy, x2 + dx, y2 + dy, x + dx, y + dy)
+            }
+            Segment::Close => Segment::Close,
+        }
+    }
+}
+
+/// A filled shape - any number of closed outlines, filled by the nonzero
+/// winding rule so the holes in glyphs stay open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    /// 0 for black through 255 for white, as in the raster labels
+    pub luma: u8,
+    pub segments: Vec<Segment>,
+}
+
+impl Path {
+    fn new(luma: u8) -> Self {
+        Path {
+            luma,
+            segments: Vec::new(),
+        }
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.segments.extend([
+            Segment::MoveTo(x, y),
+            Segment::LineTo(x + width, y),
+            Segment::LineTo(x + width, y + height),
+            Segment::LineTo(x, y + height),
+            Segment::Close,
+        ]);
+    }
+}
+
+impl OutlineBuilder for Path {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.segments.push(Segment::MoveTo(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.segments.push(Segment::LineTo(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.segments.push(Segment::QuadTo(x1, y1, x, y));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.segments.push(Segment::CurveTo(x1, y1, x2, y2, x, y));
+    }
+
+    fn close(&mut self) {
+        self.segments.push(Segment::Close);
+    }
+}
+
+/// Draw a laid out label as paths, matching what `util::generate_qr_label`
+/// rasterizes: text and label shaded by the layout's style, with the code
+/// on a light box.
+pub fn label_paths(
+    state: &AppState,
+    label: &LabelData,
+    label_layout: &LabelLayout,
+    layout: &Layout,
+) -> Result<Vec<Path>, String> {
+    let style = label_layout.style;
+    let mut background = Path::new(style.background());
+    background.rect(0.0, 0.0, layout.width as f32, layout.height as f32);
+
+    let (code_x, code_y) = (layout.code_x as f32, layout.code_y as f32);
+    let (code_width, code_height) = (layout.code_width as f32, layout.code_height as f32);
+    let mut quiet_zone = Path::new(255);
+    quiet_zone.rect(code_x, code_y, code_width, code_height);
+
+    let symbol = crate::util::encode_symbol(state, label, label_layout)?;
+    let placement = symbol.place(code_width, code_height, false)?;
+    let mut modules = Path::new(0);
+    // runs of dark modules along a row are drawn as one rectangle
+    for (row, column, run) in symbol.dark_runs() {
+        modules.rect(
+            code_x + placement.x + placement.module_width * column as f32,
+            code_y + placement.y + placement.module_height * row as f32,
+            placement.module_width * run as f32,
+            placement.module_height,
+        );
+    }
+
+    let mut text = Path::new(style.foreground());
+    for line in &layout.lines {
+        for glyph in state.fonts.glyphs(line) {
+            // outlined around the glyph's origin, then moved into place
+            let start = text.segments.len();
+            glyph.unpositioned().build_outline(&mut text);
+            let origin = glyph.position();
+            for segment in &mut text.segments[start..] {
+                *segment = segment.translate(origin.x, origin.y);
+            }
+        }
+    }
+
+    Ok(vec![background, quiet_zone, modules, text])
+}
+
+/// Render a label on its own as an SVG document, sized in millimetres.
+pub fn label_svg(
+    state: &AppState,
+    label: &LabelData,
+    template: &LabelTemplate,
+) -> Result<String, String> {
+    let (width, height) = template.label_pixels();
+    let layout = crate::layout::layout(&state.fonts, label, &template.layout, width, height);
+
+    let mut svg = String::new();
+    write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}mm" height="{}mm" viewBox="0 0 {} {}">"#,
+        template.label_width, template.label_height, width, height
+    )
+    .unwrap();
+    for path in label_paths(state, label, &template.layout, &layout)? {
+        if path.segments.is_empty() {
+            continue;
+        }
+        write!(svg, r#"<path fill="rgb({0},{0},{0})" d=""#, path.luma).unwrap();
+        for segment in path.segments {
+            match segment {
+                Segment::MoveTo(x, y) => write!(svg, "M{} {}", x, y),
+                Segment::LineTo(x, y) => write!(svg, "L{} {}", x, y),
+                Segment::QuadTo(x1, y1, x, y) => write!(svg, "Q{} {} {} {}", x1, y1, x, y),
+                Segment::CurveTo(x1, y1, x2, y2, x, y) => {
+                    write!(svg, "C{} {} {} {} {} {}", x1, y1, x2, y2, x, y)
+                }
+                Segment::Close => write!(svg, "Z"),
+            }
+            .unwrap();
+        }
+        svg.push_str(r#""/>"#);
+    }
+    svg.push_str("</svg>");
+    Ok(svg)
+}
+
+/// Draw a label onto a PDF page with its bottom left corner at `(x, y)`.
+pub fn add_label_to_layer(
+    state: &AppState,
+    layer: &PdfLayerReference,
+    label: &LabelData,
+    template: &LabelTemplate,
+    (x, y): (Mm, Mm),
+) -> Result<(), String> {
+    let (width, height) = template.label_pixels();
+    let layout = crate::layout::layout(&state.fonts, label, &template.layout, width, height);
+    let paths = label_paths(state, label, &template.layout, &layout)?;
+
+    // map label pixels, y down from the top of the label, onto PDF points
+    let points_per_pixel = (72.0 / template.dpi) as f32;
+    let top = Mm(y.0 + template.label_height);
+    let number = |n: f32| n.into();
+    let op = |operator: &str, operands: Vec<f32>| {
+        Operation::new(operator, operands.into_iter().map(number).collect())
+    };
+
+    layer.add_operation(op("q", vec![]));
+    layer.add_operation(op(
+        "cm",
+        vec![
+            points_per_pixel,
+            0.0,
+            0.0,
+            -points_per_pixel,
+            x.into_pt().0 as f32,
+            top.into_pt().0 as f32,
+        ],
+    ));
+    for path in paths {
+        if path.segments.is_empty() {
+            continue;
+        }
+        layer.add_operation(op("g", vec![path.luma as f32 / 255.0]));
+        let mut current = (0.0, 0.0);
+        for segment in path.segments {
+            layer.add_operation(match segment {
+                Segment::MoveTo(x, y) => {
+                    current = (x, y);
+                    op("m", vec![x, y])
+                }
+                Segment::LineTo(x, y) => {
+                    current = (x, y);
+                    op("l", vec![x, y])
+                }
+                Segment::QuadTo(x1, y1, x, y) => {
+                    // PDF only has cubic curves - raise the quadratic's degree
+                    let (x0, y0) = current;
+                    current = (x, y);
+                    op(
+                        "c",
+                        vec![
+                            x0 + 2.0 / 3.0 * (x1 - x0),
+                            y0 + 2.0 / 3.0 * (y1 - y0),
+                            x + 2.0 / 3.0 * (x1 - x),
+                            y + 2.0 / 3.0 * (y1 - y),
+                            x,
+                            y,
+                        ],
+                    )
+                }
+                Segment::CurveTo(x1, y1, x2, y2, x, y) => {
+                    current = (x, y);
+                    op("c", vec![x1, y1, x2, y2, x, y])
+                }
+                Segment::Close => op("h", vec![]),
+            });
+        }
+        layer.add_operation(op("f", vec![]));
+    }
+    layer.add_operation(op("Q", vec![]));
+
+    Ok(())
+}